@@ -5,10 +5,14 @@ import "./tailwind.css";
 import * as L from "leaflet";
 import "leaflet/dist/leaflet.css";
 import staticLocations from "./stations_test_data.json";
+import { parseStationsBinary, StationBuffers } from "./stations_binary.ts";
 
 const useStaticLocations = false;
-const locationsUrl =
-  "https://trip-atlas.fsn1.your-objectstorage.com/test-data/stations_test_data.json";
+// Written by `export-station-locations --format binary` or `--format json`.
+const useBinaryLocations = true;
+const locationsUrl = useBinaryLocations
+  ? "https://trip-atlas.fsn1.your-objectstorage.com/test-data/stations_test_data.bin"
+  : "https://trip-atlas.fsn1.your-objectstorage.com/test-data/stations_test_data.json";
 
 interface SourceJson {
   stations: StationInfo[];
@@ -21,13 +25,31 @@ interface StationInfo {
   time?: number;
 }
 
-async function main() {
-  let locations = (
+async function loadStations(): Promise<StationBuffers> {
+  if (!useStaticLocations && useBinaryLocations) {
+    const response = await fetch(locationsUrl);
+    return parseStationsBinary(await response.arrayBuffer());
+  }
+  const locations = (
     useStaticLocations
       ? staticLocations
       : await (await fetch(locationsUrl)).json()
   ) as SourceJson;
 
+  const positions = new Float32Array(locations.stations.length * 2);
+  const times = new Float32Array(locations.stations.length);
+  for (let i = 0; i < locations.stations.length; i++) {
+    const station = locations.stations[i];
+    positions[i * 2] = station.longitude;
+    positions[i * 2 + 1] = station.latitude;
+    times[i] = station.time ?? Number.MAX_VALUE;
+  }
+  return { count: locations.stations.length, positions, times };
+}
+
+async function main() {
+  const stations = await loadStations();
+
   createRoot(document.getElementById("root")!).render(
     <StrictMode>
       <App />
@@ -74,13 +96,13 @@ async function main() {
       '<a href="https://www.openstreetmap.org/copyright">OpenStreetMap</a>',
   }).addTo(map);
 
-  await addOverlayCanvas(map, locations);
+  await addOverlayCanvas(map, stations);
 }
 
 import vertexShaderSrc from "./test_vertex_shader.glsl";
 import fragmentShaderSrc from "./test_fragment_shader.glsl";
 
-async function addOverlayCanvas(map: L.Map, locations: SourceJson) {
+async function addOverlayCanvas(map: L.Map, stations: StationBuffers) {
   const canvas = document.getElementById(
     "map-container-overlay"
   )! as HTMLCanvasElement;
@@ -98,22 +120,15 @@ async function addOverlayCanvas(map: L.Map, locations: SourceJson) {
     -1.0, -1.0, 1.0, -1.0, 1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, 1.0,
   ]);
 
-  const positions = new Float32Array(locations.stations.length * 2);
-  const times = new Float32Array(locations.stations.length);
-  for (let i = 0; i < locations.stations.length; i++) {
-    const station = locations.stations[i];
-    positions[i * 2] = station.longitude;
-    positions[i * 2 + 1] = station.latitude;
-    times[i] = station.time ?? Number.MAX_VALUE;
-  }
-
   const locationsAttrBuffer = gl.createBuffer()!;
   gl.bindBuffer(gl.ARRAY_BUFFER, locationsAttrBuffer);
-  gl.bufferData(gl.ARRAY_BUFFER, positions, gl.STATIC_DRAW);
+  gl.bufferData(gl.ARRAY_BUFFER, stations.positions, gl.STATIC_DRAW);
 
   const timesAttrBuffer = gl.createBuffer()!;
   gl.bindBuffer(gl.ARRAY_BUFFER, timesAttrBuffer);
-  gl.bufferData(gl.ARRAY_BUFFER, times, gl.STATIC_DRAW);
+  gl.bufferData(gl.ARRAY_BUFFER, stations.times, gl.STATIC_DRAW);
+  const timesType =
+    stations.times instanceof Uint32Array ? gl.UNSIGNED_INT : gl.FLOAT;
 
   const quadOffsetAttrBuffer = gl.createBuffer()!;
   gl.bindBuffer(gl.ARRAY_BUFFER, quadOffsetAttrBuffer);
@@ -139,7 +154,7 @@ async function addOverlayCanvas(map: L.Map, locations: SourceJson) {
     gl.vertexAttribDivisor(attrs.locations, 1);
 
     gl.bindBuffer(gl.ARRAY_BUFFER, timesAttrBuffer);
-    gl.vertexAttribPointer(attrs.times, 1, gl.FLOAT, false, 0, 0);
+    gl.vertexAttribPointer(attrs.times, 1, timesType, false, 0, 0);
     gl.enableVertexAttribArray(attrs.times);
     gl.vertexAttribDivisor(attrs.times, 1);
 
@@ -184,7 +199,7 @@ async function addOverlayCanvas(map: L.Map, locations: SourceJson) {
       sizeByZoom.get(Math.round(mapZoom)) ?? 2.0
     );
 
-    gl.drawArraysInstanced(gl.TRIANGLES, 0, 6, locations.stations.length);
+    gl.drawArraysInstanced(gl.TRIANGLES, 0, 6, stations.count);
   }
 
   render();
@@ -0,0 +1,53 @@
+// Decoder for the binary station format written by the server, see `write_binary` in
+// `server/src/station_output.rs`.
+
+const magic = "TAST";
+const supportedVersion = 1;
+const flagHasNames = 1;
+
+export interface StationBuffers {
+  count: number;
+  // Interleaved longitude and latitude per station.
+  positions: Float32Array;
+  // Seconds, `0xffffffff` for stations without time in the binary format.
+  times: Uint32Array | Float32Array;
+  names?: string[];
+}
+
+export function parseStationsBinary(buffer: ArrayBuffer): StationBuffers {
+  const header = new DataView(buffer, 0, 16);
+  const foundMagic = String.fromCharCode(
+    ...new Uint8Array(buffer, 0, magic.length)
+  );
+  if (foundMagic !== magic) {
+    // Compressed files are only readable if the server sets `Content-Encoding`.
+    throw new Error("Not an uncompressed binary stations file");
+  }
+  const version = header.getUint32(4, true);
+  if (version !== supportedVersion) {
+    throw new Error(`Unsupported binary stations version ${version}`);
+  }
+  const count = header.getUint32(8, true);
+  const flags = header.getUint32(12, true);
+
+  let offset = 16;
+  const positions = new Float32Array(buffer, offset, count * 2);
+  offset += count * 2 * 4;
+  const times = new Uint32Array(buffer, offset, count);
+  offset += count * 4;
+
+  let names: string[] | undefined = undefined;
+  if (flags & flagHasNames) {
+    const nameOffsets = new Uint32Array(buffer, offset, count + 1);
+    offset += (count + 1) * 4;
+    const nameBytes = new Uint8Array(buffer, offset);
+    const decoder = new TextDecoder();
+    names = [];
+    for (let i = 0; i < count; i++) {
+      names.push(
+        decoder.decode(nameBytes.subarray(nameOffsets[i], nameOffsets[i + 1]))
+      );
+    }
+  }
+  return { count, positions, times, names };
+}
@@ -0,0 +1,77 @@
+use anyhow::Result;
+use std::{collections::HashSet, path::Path};
+
+use crate::{
+    prepare_direct_connections_rkyv::{self, NO_AGENCY},
+    prepare_gtfs_as_rkyv,
+};
+
+#[derive(Debug, Clone, Default)]
+struct AgencyStatistics {
+    routes_num: usize,
+    trips_num: usize,
+    connections_num: usize,
+    served_stations: HashSet<u32>,
+}
+
+/// Prints how large the network of every agency is, so that the coverage of different
+/// operators can be compared.
+pub async fn print_agency_statistics(gtfs_folder_path: &Path) -> Result<()> {
+    let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
+    let all_connections_rkyv =
+        prepare_direct_connections_rkyv::load_direct_connections_rkyv(gtfs_folder_path).await?;
+
+    // The last entry is used for routes without known agency.
+    let mut statistics = vec![AgencyStatistics::default(); gtfs_rkyv.agencies.len() + 1];
+    let unknown_agency_i = gtfs_rkyv.agencies.len();
+
+    // Resolved like for the connections, so that the counts match their agency indices.
+    let agency_i = |agency_i: u32| match agency_i {
+        NO_AGENCY => unknown_agency_i,
+        agency_i => agency_i as usize,
+    };
+    for (_, route_agency_i) in prepare_direct_connections_rkyv::route_info_by_route_i(&gtfs_rkyv) {
+        statistics[agency_i(route_agency_i)].routes_num += 1;
+    }
+    for (_, trip_agency_i) in prepare_direct_connections_rkyv::route_info_by_trip_i(&gtfs_rkyv) {
+        statistics[agency_i(trip_agency_i)].trips_num += 1;
+    }
+
+    for (from_station_i, station) in all_connections_rkyv.stations.iter().enumerate() {
+        for connection in station.connections.iter() {
+            let agency_statistics = &mut statistics[agency_i(connection.agency_i.to_native())];
+            agency_statistics.connections_num += 1;
+            agency_statistics
+                .served_stations
+                .insert(from_station_i as u32);
+            agency_statistics
+                .served_stations
+                .insert(connection.to_station_i.to_native());
+        }
+    }
+
+    println!(
+        "{:<40} {:>8} {:>10} {:>12} {:>10}",
+        "Agency", "Routes", "Trips", "Connections", "Stations"
+    );
+    for (agency_i, agency_statistics) in statistics.iter().enumerate() {
+        let name = match gtfs_rkyv.agencies.get(agency_i) {
+            Some(agency) => agency.name.to_string(),
+            None => {
+                if agency_statistics.routes_num == 0 && agency_statistics.connections_num == 0 {
+                    continue;
+                }
+                "<unknown agency>".to_string()
+            }
+        };
+        println!(
+            "{:<40} {:>8} {:>10} {:>12} {:>10}",
+            name,
+            agency_statistics.routes_num,
+            agency_statistics.trips_num,
+            agency_statistics.connections_num,
+            agency_statistics.served_stations.len()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use anyhow::Result;
+use std::{io::Write, path::Path};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum Compression {
+    None,
+    Zstd,
+    /// Can be decoded by browsers directly when served with `Content-Encoding: br`.
+    Brotli,
+}
+
+pub fn write_compressed_file(path: &Path, data: &[u8], compression: Compression) -> Result<()> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    match compression {
+        Compression::None => file.write_all(data)?,
+        Compression::Zstd => zstd::stream::copy_encode(data, &mut file, 19)?,
+        Compression::Brotli => {
+            let params = brotli::enc::BrotliEncoderParams {
+                quality: 11,
+                ..Default::default()
+            };
+            brotli::BrotliCompress(&mut &data[..], &mut file, &params)?;
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
@@ -0,0 +1,279 @@
+use anyhow::Result;
+
+use crate::{
+    gtfs_rkyv::{self, ArchivedGtfsRouteType},
+    prepare_direct_connections_rkyv,
+};
+
+/// Transport modes that connections can be filtered by. Each mode corresponds to one bit in
+/// `ConnectionToStation.route_type_mask`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransportMode {
+    Tramway,
+    Subway,
+    /// Trains that don't have one of the more specific rail modes. Filtering by it includes
+    /// all of them.
+    Rail,
+    Bus,
+    Ferry,
+    CableCar,
+    Gondola,
+    Funicular,
+    Coach,
+    Air,
+    Taxi,
+    Other,
+    /// Extended route type 101.
+    HighSpeedRail,
+    /// Extended route types 102, 103, 105 and 114.
+    LongDistanceRail,
+    /// Extended route type 106.
+    RegionalRail,
+    /// Extended route type 109.
+    SuburbanRail,
+}
+
+impl TransportMode {
+    pub fn from_route(route: &gtfs_rkyv::ArchivedGtfsRoute) -> Self {
+        match route.route_type_code.to_native() {
+            101 => TransportMode::HighSpeedRail,
+            102 | 103 | 105 | 114 => TransportMode::LongDistanceRail,
+            106 => TransportMode::RegionalRail,
+            109 => TransportMode::SuburbanRail,
+            _ => TransportMode::from_route_type(&route.route_type),
+        }
+    }
+
+    fn from_route_type(route_type: &ArchivedGtfsRouteType) -> Self {
+        match route_type {
+            ArchivedGtfsRouteType::Tramway => TransportMode::Tramway,
+            ArchivedGtfsRouteType::Subway => TransportMode::Subway,
+            ArchivedGtfsRouteType::Rail => TransportMode::Rail,
+            ArchivedGtfsRouteType::Bus => TransportMode::Bus,
+            ArchivedGtfsRouteType::Ferry => TransportMode::Ferry,
+            ArchivedGtfsRouteType::CableCar => TransportMode::CableCar,
+            ArchivedGtfsRouteType::Gondola => TransportMode::Gondola,
+            ArchivedGtfsRouteType::Funicular => TransportMode::Funicular,
+            ArchivedGtfsRouteType::Coach => TransportMode::Coach,
+            ArchivedGtfsRouteType::Air => TransportMode::Air,
+            ArchivedGtfsRouteType::Taxi => TransportMode::Taxi,
+            ArchivedGtfsRouteType::Other(_) => TransportMode::Other,
+        }
+    }
+
+    pub fn mask_bit(self) -> u16 {
+        1 << (self as u16)
+    }
+
+    /// Bits of the connections that filtering by this mode refers to.
+    fn filter_mask(self) -> u16 {
+        match self {
+            TransportMode::Rail => [
+                TransportMode::Rail,
+                TransportMode::HighSpeedRail,
+                TransportMode::LongDistanceRail,
+                TransportMode::RegionalRail,
+                TransportMode::SuburbanRail,
+            ]
+            .iter()
+            .fold(0, |mask, mode| mask | mode.mask_bit()),
+            _ => self.mask_bit(),
+        }
+    }
+}
+
+/// Restricts which connections can be used by a search.
+#[derive(Debug, Clone)]
+pub struct ConnectionFilter {
+    pub allowed_route_types: u16,
+    /// Indexed by agency index. `None` if connections of all agencies are allowed.
+    pub allowed_agencies: Option<Vec<bool>>,
+    /// Whether connections whose route does not reference a known agency are allowed when
+    /// `allowed_agencies` is set.
+    pub unknown_agency_allowed: bool,
+    /// Only change platforms on pathways without stairs or escalators.
+    pub step_free: bool,
+    /// Only use connections whose trip and stops are wheelchair accessible.
+    pub wheelchair_accessible_only: bool,
+}
+
+impl ConnectionFilter {
+    pub fn allows(
+        &self,
+        connection: &prepare_direct_connections_rkyv::ArchivedConnectionToStation,
+    ) -> bool {
+        self.allows_attributes(
+            connection.route_type_mask.to_native(),
+            connection.agency_i.to_native(),
+            connection.wheelchair_accessible,
+        )
+    }
+
+    /// Same as `allows`, for connections that are not taken from `AllConnections`.
+    pub fn allows_attributes(
+        &self,
+        route_type_mask: u16,
+        agency_i: u32,
+        wheelchair_accessible: bool,
+    ) -> bool {
+        if route_type_mask & self.allowed_route_types == 0 {
+            return false;
+        }
+        if self.wheelchair_accessible_only && !wheelchair_accessible {
+            return false;
+        }
+        if let Some(allowed_agencies) = &self.allowed_agencies {
+            return allowed_agencies
+                .get(agency_i as usize)
+                .copied()
+                .unwrap_or(self.unknown_agency_allowed);
+        }
+        true
+    }
+}
+
+impl Default for ConnectionFilter {
+    fn default() -> Self {
+        ConnectionFilter {
+            allowed_route_types: u16::MAX,
+            allowed_agencies: None,
+            unknown_agency_allowed: true,
+            step_free: false,
+            wheelchair_accessible_only: false,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ConnectionFilterArgs {
+    /// Only use connections of this mode, can be passed multiple times.
+    #[arg(long = "mode", value_enum)]
+    pub modes: Vec<TransportMode>,
+    /// Don't use connections of this mode, can be passed multiple times.
+    #[arg(long = "exclude-mode", value_enum)]
+    pub excluded_modes: Vec<TransportMode>,
+    /// Only use connections of the agency with this id or name, can be passed multiple times.
+    #[arg(long = "agency")]
+    pub agencies: Vec<String>,
+    /// Don't use connections of the agency with this id or name, can be passed multiple times.
+    #[arg(long = "exclude-agency")]
+    pub excluded_agencies: Vec<String>,
+    /// Only change platforms where the station's pathways allow it without stairs or
+    /// escalators.
+    #[arg(long)]
+    pub step_free: bool,
+    /// Only use wheelchair accessible trips and stops. Implies `--step-free`.
+    #[arg(long)]
+    pub wheelchair: bool,
+}
+
+impl ConnectionFilterArgs {
+    pub fn to_filter(&self, gtfs_rkyv: &gtfs_rkyv::ArchivedGtfsData) -> Result<ConnectionFilter> {
+        let mut filter = ConnectionFilter {
+            step_free: self.step_free || self.wheelchair,
+            wheelchair_accessible_only: self.wheelchair,
+            ..ConnectionFilter::default()
+        };
+        if !self.modes.is_empty() {
+            filter.allowed_route_types = self
+                .modes
+                .iter()
+                .fold(0, |mask, mode| mask | mode.filter_mask());
+        }
+        for mode in &self.excluded_modes {
+            filter.allowed_route_types &= !mode.filter_mask();
+        }
+
+        if !self.agencies.is_empty() || !self.excluded_agencies.is_empty() {
+            let mut allowed_agencies = vec![self.agencies.is_empty(); gtfs_rkyv.agencies.len()];
+            for agency in &self.agencies {
+                allowed_agencies[find_agency_index(gtfs_rkyv, agency)?] = true;
+            }
+            for agency in &self.excluded_agencies {
+                allowed_agencies[find_agency_index(gtfs_rkyv, agency)?] = false;
+            }
+            filter.allowed_agencies = Some(allowed_agencies);
+            filter.unknown_agency_allowed = self.agencies.is_empty();
+        }
+        Ok(filter)
+    }
+}
+
+fn find_agency_index(gtfs_rkyv: &gtfs_rkyv::ArchivedGtfsData, id_or_name: &str) -> Result<usize> {
+    gtfs_rkyv
+        .agencies
+        .iter()
+        .position(|agency| {
+            agency.name.as_str() == id_or_name
+                || agency.id.as_ref().map(|id| id.as_str()) == Some(id_or_name)
+        })
+        .ok_or_else(|| anyhow::anyhow!("Unknown agency {:?}", id_or_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs_rkyv::{GtfsRoute, GtfsRouteType};
+
+    fn mode_of_route(route_type: GtfsRouteType, route_type_code: i16) -> TransportMode {
+        let route = GtfsRoute {
+            id: "R".to_string(),
+            short_name: None,
+            long_name: None,
+            description: None,
+            url: None,
+            route_type,
+            route_type_code,
+            agency_id: None,
+            color: None,
+            text_color: None,
+            sort_order: None,
+        };
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&route).unwrap();
+        let route =
+            rkyv::access::<gtfs_rkyv::ArchivedGtfsRoute, rkyv::rancor::Error>(&bytes).unwrap();
+        TransportMode::from_route(route)
+    }
+
+    #[test]
+    fn keeps_extended_rail_types_apart() {
+        assert_eq!(mode_of_route(GtfsRouteType::Rail, 2), TransportMode::Rail);
+        assert_eq!(mode_of_route(GtfsRouteType::Rail, 100), TransportMode::Rail);
+        assert_eq!(
+            mode_of_route(GtfsRouteType::Rail, 101),
+            TransportMode::HighSpeedRail
+        );
+        assert_eq!(
+            mode_of_route(GtfsRouteType::Rail, 102),
+            TransportMode::LongDistanceRail
+        );
+        assert_eq!(
+            mode_of_route(GtfsRouteType::Rail, 106),
+            TransportMode::RegionalRail
+        );
+        assert_eq!(
+            mode_of_route(GtfsRouteType::Rail, 109),
+            TransportMode::SuburbanRail
+        );
+        assert_eq!(mode_of_route(GtfsRouteType::Bus, 700), TransportMode::Bus);
+    }
+
+    #[test]
+    fn rail_filter_includes_the_specific_rail_modes() {
+        let rail_mask = TransportMode::Rail.filter_mask();
+        for mode in [
+            TransportMode::Rail,
+            TransportMode::HighSpeedRail,
+            TransportMode::LongDistanceRail,
+            TransportMode::RegionalRail,
+            TransportMode::SuburbanRail,
+        ] {
+            assert_ne!(rail_mask & mode.mask_bit(), 0);
+        }
+        assert_eq!(rail_mask & TransportMode::Bus.mask_bit(), 0);
+        assert_eq!(
+            TransportMode::RegionalRail.filter_mask(),
+            TransportMode::RegionalRail.mask_bit()
+        );
+    }
+}
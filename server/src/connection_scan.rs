@@ -0,0 +1,421 @@
+use anyhow::Result;
+use chrono::{Days, NaiveDate};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use crate::{
+    connection_filter::{ConnectionFilter, ConnectionFilterArgs},
+    find_latest_departures, find_optimal_paths, gtfs_rkyv, prepare_direct_connections_rkyv,
+    prepare_gtfs_as_rkyv,
+    station_output::{self, OutputStation, StationOutputArgs},
+    timezones,
+};
+
+/// The search can not look further ahead than the trips of the following service day reach.
+const MAX_HORIZON_MINUTES: u32 = 24 * 60;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct TimetableSearchArgs {
+    /// Service day of the departure in the format `YYYY-MM-DD`.
+    #[arg(long)]
+    pub date: NaiveDate,
+    /// Earliest departure at the start stations, e.g. `23:30`. Times after midnight can be
+    /// given as `25:15` or as `01:15` on the next date.
+    #[arg(long)]
+    pub departure_time: String,
+    /// Only connections departing within this many minutes after the departure time are used.
+    #[arg(long, default_value_t = 6 * 60)]
+    pub horizon_minutes: u32,
+}
+
+/// A ride between two consecutive stops of a trip on a specific service day. Times are in
+/// seconds since the start of the searched service day.
+#[derive(Debug, Clone, Copy)]
+struct TimetableConnection {
+    departure: u32,
+    arrival: u32,
+    from_stop_i: u32,
+    to_stop_i: u32,
+    from_station_i: u32,
+    to_station_i: u32,
+    /// Index of the trip on its service day, connections of one trip run share it.
+    trip_run_i: u32,
+    route_type_mask: u16,
+    agency_i: u32,
+    wheelchair_accessible: bool,
+}
+
+/// Finds the earliest arrival at every station when departing at a given time on a given day.
+/// Unlike `find_optimal_paths`, this follows the actual timetable, including trips of the
+/// previous service day that run after midnight and trips of the next service day.
+pub async fn find_timetable_arrivals(
+    gtfs_folder_path: &Path,
+    start_station_names: &[String],
+    search: &TimetableSearchArgs,
+    output: &StationOutputArgs,
+    connection_filter: &ConnectionFilterArgs,
+) -> Result<()> {
+    if search.horizon_minutes > MAX_HORIZON_MINUTES {
+        anyhow::bail!("The horizon can be at most {} minutes", MAX_HORIZON_MINUTES);
+    }
+    let departure_time = find_latest_departures::parse_time_of_day(&search.departure_time)?;
+
+    let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
+    let all_connections_rkyv =
+        prepare_direct_connections_rkyv::load_direct_connections_rkyv(gtfs_folder_path).await?;
+    let connection_filter = connection_filter.to_filter(&gtfs_rkyv)?;
+
+    let start_station_names: Vec<&str> = start_station_names
+        .iter()
+        .map(|name| name.as_str())
+        .collect();
+    let start_station_indices = find_optimal_paths::find_station_indices_by_names(
+        &gtfs_rkyv,
+        &all_connections_rkyv,
+        &start_station_names,
+    );
+    if start_station_indices.is_empty() {
+        anyhow::bail!("None of the given station names was found");
+    }
+
+    let connections = collect_timetable_connections(
+        &gtfs_rkyv,
+        &all_connections_rkyv.stations,
+        search.date,
+        departure_time,
+        departure_time + search.horizon_minutes * 60,
+    )?;
+    log::info!("Scanning {} connections", connections.len());
+
+    let earliest_arrivals = scan_connections(
+        &all_connections_rkyv.stations,
+        &connections,
+        &start_station_indices,
+        departure_time,
+        &connection_filter,
+    );
+
+    let mut stations = vec![];
+    for (station, earliest_arrival) in all_connections_rkyv.stations.iter().zip(earliest_arrivals) {
+        let Some(earliest_arrival) = earliest_arrival else {
+            continue;
+        };
+        let stop = &gtfs_rkyv.stops[station.main_stop_i.to_native() as usize];
+        let (Some(name), Some(latitude), Some(longitude)) = (
+            stop.name.as_ref(),
+            stop.latitude.as_ref(),
+            stop.longitude.as_ref(),
+        ) else {
+            continue;
+        };
+        stations.push(OutputStation {
+            id: stop.id.to_string(),
+            name: name.to_string(),
+            latitude: latitude.to_native(),
+            longitude: longitude.to_native(),
+            time: Some(earliest_arrival),
+        });
+    }
+    station_output::write_stations(&stations, output)
+}
+
+/// Collects the connections of the trips running on the day before, the day itself and the day
+/// after that depart within `[earliest_departure, latest_departure]`, sorted by departure.
+/// Times of the neighbouring days are shifted by the real length of the days in between, which
+/// differs from 24 hours across daylight saving time changes.
+fn collect_timetable_connections(
+    gtfs_rkyv: &gtfs_rkyv::ArchivedGtfsData,
+    stations: &[prepare_direct_connections_rkyv::ArchivedConnectionsFromStation],
+    date: NaiveDate,
+    earliest_departure: u32,
+    latest_departure: u32,
+) -> Result<Vec<TimetableConnection>> {
+    let feed_timezone = gtfs_rkyv.feed_timezone()?;
+    let day_start = timezones::service_day_start(date, feed_timezone);
+
+    let stop_index_by_id: HashMap<&str, usize> = gtfs_rkyv
+        .stops
+        .iter()
+        .enumerate()
+        .map(|(stop_i, stop)| (stop.id.as_str(), stop_i))
+        .collect();
+    let station_index_by_stop_i = station_index_by_stop_i(gtfs_rkyv, &stop_index_by_id, stations);
+    let wheelchair_boarding_by_stop_i: Vec<bool> = (0..gtfs_rkyv.stops.len())
+        .map(|stop_i| {
+            prepare_direct_connections_rkyv::is_wheelchair_boarding_possible(
+                &gtfs_rkyv.stops,
+                &stop_index_by_id,
+                stop_i,
+            )
+        })
+        .collect();
+    let route_info_by_trip_i = prepare_direct_connections_rkyv::route_info_by_trip_i(gtfs_rkyv);
+
+    let mut connections = vec![];
+    let mut trip_runs_num = 0;
+    for service_date in [
+        date.checked_sub_days(Days::new(1)),
+        Some(date),
+        date.checked_add_days(Days::new(1)),
+    ] {
+        let Some(service_date) = service_date else {
+            continue;
+        };
+        let day_shift =
+            (timezones::service_day_start(service_date, feed_timezone) - day_start).num_seconds();
+        let running_service_ids: HashSet<&str> = gtfs_rkyv
+            .services
+            .iter()
+            .filter(|service| service.runs_on(service_date))
+            .map(|service| service.id.as_str())
+            .collect();
+
+        for (trip_i, trip) in gtfs_rkyv.trips.iter().enumerate() {
+            if !running_service_ids.contains(trip.service_id.as_str()) {
+                continue;
+            }
+            let (mode, agency_i) = route_info_by_trip_i[trip_i];
+            let trip_wheelchair_accessible = matches!(
+                trip.wheelchair_accessible,
+                gtfs_rkyv::ArchivedGtfsAvailability::Available
+            );
+            let trip_run_i = trip_runs_num;
+            trip_runs_num += 1;
+
+            let stop_times = &gtfs_rkyv.stop_times;
+            let stop_time_range = stop_times.trip_range(trip_i);
+            for stop_time_i in stop_time_range.start..stop_time_range.end.saturating_sub(1) {
+                let (Some(departure), Some(arrival)) = (
+                    stop_times.departure_time(stop_time_i),
+                    stop_times.arrival_time(stop_time_i + 1),
+                ) else {
+                    continue;
+                };
+                let departure = departure as i64 + day_shift;
+                let arrival = arrival as i64 + day_shift;
+                if departure < earliest_departure as i64
+                    || departure > latest_departure as i64
+                    || arrival < departure
+                {
+                    continue;
+                }
+                let from_stop_i = stop_times.stop_i(stop_time_i) as u32;
+                let to_stop_i = stop_times.stop_i(stop_time_i + 1) as u32;
+                let (Some(from_station_i), Some(to_station_i)) = (
+                    station_index_by_stop_i[from_stop_i as usize],
+                    station_index_by_stop_i[to_stop_i as usize],
+                ) else {
+                    continue;
+                };
+                connections.push(TimetableConnection {
+                    departure: departure as u32,
+                    arrival: arrival as u32,
+                    from_stop_i,
+                    to_stop_i,
+                    from_station_i,
+                    to_station_i,
+                    trip_run_i,
+                    route_type_mask: mode.mask_bit(),
+                    agency_i,
+                    wheelchair_accessible: trip_wheelchair_accessible
+                        && wheelchair_boarding_by_stop_i[from_stop_i as usize]
+                        && wheelchair_boarding_by_stop_i[to_stop_i as usize],
+                });
+            }
+        }
+    }
+    // Connections with the same departure are ordered by arrival so that zero-duration
+    // connections of a trip are scanned in order.
+    connections.sort_by_key(|connection| (connection.departure, connection.arrival));
+    Ok(connections)
+}
+
+/// Station of every stop that vehicles can be boarded at, following the same rules as the
+/// creation of `AllConnections`.
+pub fn station_index_by_stop_i(
+    gtfs_rkyv: &gtfs_rkyv::ArchivedGtfsData,
+    stop_index_by_id: &HashMap<&str, usize>,
+    stations: &[prepare_direct_connections_rkyv::ArchivedConnectionsFromStation],
+) -> Vec<Option<u32>> {
+    let station_index_by_root_stop_i: HashMap<usize, u32> = stations
+        .iter()
+        .enumerate()
+        .map(|(station_i, station)| (station.main_stop_i.to_native() as usize, station_i as u32))
+        .collect();
+    gtfs_rkyv
+        .stops
+        .iter()
+        .enumerate()
+        .map(|(stop_i, stop)| {
+            let root_stop_i = prepare_direct_connections_rkyv::find_root_stop_i(
+                &gtfs_rkyv.stops,
+                stop_index_by_id,
+                stop_i,
+            )?;
+            if !stop.location_type.is_boarding_location() && root_stop_i != stop_i {
+                return None;
+            }
+            station_index_by_root_stop_i.get(&root_stop_i).copied()
+        })
+        .collect()
+}
+
+/// Connection scan: goes through the connections in the order of their departure and keeps the
+/// earliest arrival per arrival slot of a station, see `arrival_slots_num`. A connection can be
+/// used if its trip was already boarded or if any platform of its station is reached early
+/// enough to walk to the platform of the connection.
+fn scan_connections(
+    stations: &[prepare_direct_connections_rkyv::ArchivedConnectionsFromStation],
+    connections: &[TimetableConnection],
+    start_station_indices: &[u32],
+    departure_time: u32,
+    connection_filter: &ConnectionFilter,
+) -> Vec<Option<u32>> {
+    let mut earliest_arrivals = vec![None; stations.len()];
+    let arrival_slots = find_optimal_paths::ArrivalSlots::new(stations);
+    let mut slot_arrivals: Vec<Option<u32>> = vec![None; arrival_slots.slots_num()];
+    for start_station_i in start_station_indices {
+        earliest_arrivals[*start_station_i as usize] = Some(departure_time);
+        slot_arrivals[arrival_slots.start_slot_i(*start_station_i)] = Some(departure_time);
+    }
+    let trip_runs_num = connections
+        .iter()
+        .map(|connection| connection.trip_run_i as usize + 1)
+        .max()
+        .unwrap_or(0);
+    let mut boarded_trip_runs = vec![false; trip_runs_num];
+
+    for connection in connections {
+        if !connection_filter.allows_attributes(
+            connection.route_type_mask,
+            connection.agency_i,
+            connection.wheelchair_accessible,
+        ) {
+            continue;
+        }
+        if !boarded_trip_runs[connection.trip_run_i as usize] {
+            let from_station = &stations[connection.from_station_i as usize];
+            let can_board = (0..from_station.arrival_slots_num()).any(|slot| {
+                let slot_i = arrival_slots.slot_i(connection.from_station_i, slot);
+                let Some(arrival) = slot_arrivals[slot_i] else {
+                    return false;
+                };
+                from_station
+                    .transfer_time(
+                        from_station.arrival_slot_stop_i(slot),
+                        connection.from_stop_i,
+                        connection_filter.step_free,
+                    )
+                    .is_some_and(|transfer_time| arrival + transfer_time <= connection.departure)
+            });
+            if !can_board {
+                continue;
+            }
+            boarded_trip_runs[connection.trip_run_i as usize] = true;
+        }
+        let to_station = &stations[connection.to_station_i as usize];
+        let to_slot_i = arrival_slots.slot_i(
+            connection.to_station_i,
+            to_station.arrival_slot(connection.to_stop_i),
+        );
+        if slot_arrivals[to_slot_i].is_none_or(|arrival| connection.arrival < arrival) {
+            slot_arrivals[to_slot_i] = Some(connection.arrival);
+        }
+        let to_station_i = connection.to_station_i as usize;
+        if earliest_arrivals[to_station_i].is_none_or(|arrival| connection.arrival < arrival) {
+            earliest_arrivals[to_station_i] = Some(connection.arrival);
+        }
+    }
+    earliest_arrivals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prepare_direct_connections_rkyv::ConnectionsFromStation;
+
+    /// Stations without pathways, except for station 1 whose platforms 10 and 11 are five
+    /// minutes apart.
+    fn stations() -> Vec<ConnectionsFromStation> {
+        (0..4)
+            .map(|station_i| {
+                let (transfer_stop_indices, transfer_times) = if station_i == 1 {
+                    (vec![10, 11], vec![0, 300, 300, 0])
+                } else {
+                    (vec![], vec![])
+                };
+                ConnectionsFromStation {
+                    main_stop_i: station_i,
+                    connections: vec![],
+                    transfer_stop_indices,
+                    step_free_transfer_times: transfer_times.clone(),
+                    transfer_times,
+                }
+            })
+            .collect()
+    }
+
+    fn connection(
+        trip_run_i: u32,
+        (from_station_i, from_stop_i): (u32, u32),
+        (to_station_i, to_stop_i): (u32, u32),
+        departure: u32,
+        arrival: u32,
+    ) -> TimetableConnection {
+        TimetableConnection {
+            departure,
+            arrival,
+            from_stop_i,
+            to_stop_i,
+            from_station_i,
+            to_station_i,
+            trip_run_i,
+            route_type_mask: 1,
+            agency_i: 0,
+            wheelchair_accessible: true,
+        }
+    }
+
+    #[test]
+    fn transfers_after_midnight() {
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&stations()).unwrap();
+        let stations = rkyv::access::<
+            rkyv::Archived<Vec<ConnectionsFromStation>>,
+            rkyv::rancor::Error,
+        >(&bytes)
+        .unwrap();
+
+        let connections = [
+            // Arrives at platform 10 at 24:10:00.
+            connection(0, (0, 0), (1, 10), 23 * 3600 + 50 * 60, 24 * 3600 + 10 * 60),
+            // Departs before the arrival.
+            connection(1, (1, 10), (3, 3), 24 * 3600, 24 * 3600 + 600),
+            // Leaves no time to walk to platform 11.
+            connection(2, (1, 11), (2, 2), 24 * 3600 + 11 * 60, 24 * 3600 + 20 * 60),
+            connection(3, (1, 11), (2, 2), 24 * 3600 + 20 * 60, 24 * 3600 + 40 * 60),
+            // Continues the first trip, which needs no transfer.
+            connection(0, (1, 10), (3, 3), 24 * 3600 + 15 * 60, 24 * 3600 + 50 * 60),
+        ];
+        let mut connections = connections.to_vec();
+        connections.sort_by_key(|connection| (connection.departure, connection.arrival));
+
+        let earliest_arrivals = scan_connections(
+            stations,
+            &connections,
+            &[0],
+            23 * 3600 + 30 * 60,
+            &ConnectionFilter::default(),
+        );
+        assert_eq!(
+            earliest_arrivals,
+            vec![
+                Some(23 * 3600 + 30 * 60),
+                Some(24 * 3600 + 10 * 60),
+                Some(24 * 3600 + 40 * 60),
+                Some(24 * 3600 + 50 * 60),
+            ]
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use anyhow::Result;
+use std::{io::Write, path::Path};
+
+use crate::{
+    connection_filter::ConnectionFilterArgs,
+    find_optimal_paths, prepare_direct_connections_rkyv, prepare_gtfs_as_rkyv,
+    station_output::OutputStation,
+    walking::{self, WalkingArgs},
+};
+
+const GRID_MAGIC: &[u8; 4] = b"TAGR";
+const GRID_VERSION: u32 = 1;
+/// Resolution of the values in the raw grid. With a tenth of a minute per unit, a 16 bit
+/// value covers more than 100 hours.
+const GRID_SECONDS_PER_UNIT: u32 = 6;
+/// Value in the raw grid for pixels that cannot be reached.
+const GRID_UNREACHABLE: u16 = u16::MAX;
+
+/// Colors of the image from short to long travel times.
+const COLOR_STOPS: [[u8; 3]; 5] = [
+    [26, 152, 80],
+    [166, 217, 106],
+    [254, 224, 139],
+    [244, 109, 67],
+    [165, 0, 38],
+];
+const COLOR_ALPHA: u8 = 200;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct HeatmapArgs {
+    /// Area that is rendered as `min_longitude,min_latitude,max_longitude,max_latitude`.
+    #[arg(long, value_delimiter = ',', allow_negative_numbers = true)]
+    pub bbox: Vec<f64>,
+    /// Width of the raster in pixels. The height follows from the aspect ratio of the area.
+    #[arg(long, default_value_t = 1024)]
+    pub width: u32,
+    /// Travel time in minutes that is mapped to the last color of the image.
+    #[arg(long, default_value_t = 90)]
+    pub max_minutes: u32,
+    /// Path of the colored PNG image.
+    #[arg(long)]
+    pub image_path: String,
+    /// Path of the raw grid with 16 bit travel times.
+    #[arg(long)]
+    pub grid_path: String,
+}
+
+/// Geographic area covered by the raster, using an equirectangular projection.
+struct Raster {
+    min_longitude: f64,
+    min_latitude: f64,
+    max_longitude: f64,
+    max_latitude: f64,
+    width: u32,
+    height: u32,
+}
+
+impl Raster {
+    fn pixel_longitude(&self, x: u32) -> f64 {
+        self.min_longitude
+            + (x as f64 + 0.5) / self.width as f64 * (self.max_longitude - self.min_longitude)
+    }
+
+    fn pixel_latitude(&self, y: u32) -> f64 {
+        self.max_latitude
+            - (y as f64 + 0.5) / self.height as f64 * (self.max_latitude - self.min_latitude)
+    }
+
+    /// Inclusive range of pixel columns whose centers lie between the given longitudes.
+    fn columns(&self, min_longitude: f64, max_longitude: f64) -> Option<(u32, u32)> {
+        let to_x = |longitude: f64| {
+            (longitude - self.min_longitude) / (self.max_longitude - self.min_longitude)
+                * self.width as f64
+                - 0.5
+        };
+        pixel_range(
+            to_x(min_longitude).ceil(),
+            to_x(max_longitude).floor(),
+            self.width,
+        )
+    }
+
+    /// Inclusive range of pixel rows whose centers lie between the given latitudes.
+    fn rows(&self, min_latitude: f64, max_latitude: f64) -> Option<(u32, u32)> {
+        let to_y = |latitude: f64| {
+            (self.max_latitude - latitude) / (self.max_latitude - self.min_latitude)
+                * self.height as f64
+                - 0.5
+        };
+        pixel_range(
+            to_y(max_latitude).ceil(),
+            to_y(min_latitude).floor(),
+            self.height,
+        )
+    }
+}
+
+fn pixel_range(first: f64, last: f64, size: u32) -> Option<(u32, u32)> {
+    let first = first.max(0.0);
+    let last = last.min(size as f64 - 1.0);
+    if first > last {
+        return None;
+    }
+    Some((first as u32, last as u32))
+}
+
+/// Renders the travel time from the start stations as a raster. Every pixel holds the earliest
+/// arrival at any station plus the time to walk from that station to the pixel.
+pub async fn export_heatmap(
+    gtfs_folder_path: &Path,
+    start_station_names: &[String],
+    heatmap: &HeatmapArgs,
+    walking: &WalkingArgs,
+    connection_filter: &ConnectionFilterArgs,
+) -> Result<()> {
+    let [min_longitude, min_latitude, max_longitude, max_latitude] = heatmap.bbox[..] else {
+        anyhow::bail!("The bounding box must consist of exactly four values");
+    };
+    if min_longitude >= max_longitude || min_latitude >= max_latitude {
+        anyhow::bail!("The bounding box must have a positive extent");
+    }
+    if heatmap.width == 0 {
+        anyhow::bail!("The width must be at least one pixel");
+    }
+    let center_latitude = (min_latitude + max_latitude) / 2.0;
+    let aspect_ratio = ((max_latitude - min_latitude) * walking::METERS_PER_DEGREE_LATITUDE)
+        / ((max_longitude - min_longitude) * walking::meters_per_degree_longitude(center_latitude));
+    let raster = Raster {
+        min_longitude,
+        min_latitude,
+        max_longitude,
+        max_latitude,
+        width: heatmap.width,
+        height: ((heatmap.width as f64 * aspect_ratio).round() as u32).max(1),
+    };
+
+    let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
+    let all_connections_rkyv =
+        prepare_direct_connections_rkyv::load_direct_connections_rkyv(gtfs_folder_path).await?;
+    let connection_filter = connection_filter.to_filter(&gtfs_rkyv)?;
+
+    let start_station_names: Vec<&str> = start_station_names
+        .iter()
+        .map(|name| name.as_str())
+        .collect();
+    let start_station_indices = find_optimal_paths::find_station_indices_by_names(
+        &gtfs_rkyv,
+        &all_connections_rkyv,
+        &start_station_names,
+    );
+    if start_station_indices.is_empty() {
+        anyhow::bail!("None of the given station names was found");
+    }
+    let station_states = find_optimal_paths::find_earliest_arrivals(
+        &all_connections_rkyv.stations,
+        &start_station_indices,
+        &connection_filter,
+    );
+    let stations = find_optimal_paths::collect_reached_stations(
+        &gtfs_rkyv,
+        &all_connections_rkyv,
+        &station_states,
+    );
+
+    let travel_times = render_travel_times(&raster, &stations, walking);
+    write_image(&raster, &travel_times, heatmap)?;
+    write_grid(&raster, &travel_times, heatmap)?;
+    Ok(())
+}
+
+/// Computes the travel time in seconds for every pixel, row by row from the north.
+fn render_travel_times(
+    raster: &Raster,
+    stations: &[OutputStation],
+    walking: &WalkingArgs,
+) -> Vec<Option<u32>> {
+    let mut travel_times = vec![None; raster.width as usize * raster.height as usize];
+    for station in stations {
+        let Some(time) = station.time else {
+            continue;
+        };
+        let meters_per_degree_longitude = walking::meters_per_degree_longitude(station.latitude);
+        let radius_longitude = walking.max_walking_distance / meters_per_degree_longitude;
+        let radius_latitude = walking.max_walking_distance / walking::METERS_PER_DEGREE_LATITUDE;
+        let (Some((min_x, max_x)), Some((min_y, max_y))) = (
+            raster.columns(
+                station.longitude - radius_longitude,
+                station.longitude + radius_longitude,
+            ),
+            raster.rows(
+                station.latitude - radius_latitude,
+                station.latitude + radius_latitude,
+            ),
+        ) else {
+            continue;
+        };
+        for y in min_y..=max_y {
+            let delta_y =
+                (raster.pixel_latitude(y) - station.latitude) * walking::METERS_PER_DEGREE_LATITUDE;
+            for x in min_x..=max_x {
+                let delta_x =
+                    (raster.pixel_longitude(x) - station.longitude) * meters_per_degree_longitude;
+                let distance = (delta_x * delta_x + delta_y * delta_y).sqrt();
+                if distance > walking.max_walking_distance {
+                    continue;
+                }
+                let pixel_time = time + (distance / walking.walking_speed).round() as u32;
+                let travel_time = &mut travel_times[(y * raster.width + x) as usize];
+                if travel_time.is_none_or(|travel_time| pixel_time < travel_time) {
+                    *travel_time = Some(pixel_time);
+                }
+            }
+        }
+    }
+    travel_times
+}
+
+fn write_image(raster: &Raster, travel_times: &[Option<u32>], heatmap: &HeatmapArgs) -> Result<()> {
+    let max_seconds = (heatmap.max_minutes * 60).max(1) as f64;
+    let mut pixels = Vec::with_capacity(travel_times.len() * 4);
+    for travel_time in travel_times {
+        match travel_time {
+            Some(travel_time) => {
+                let color = interpolate_color((*travel_time as f64 / max_seconds).min(1.0));
+                pixels.extend_from_slice(&color);
+                pixels.push(COLOR_ALPHA);
+            }
+            None => pixels.extend_from_slice(&[0, 0, 0, 0]),
+        }
+    }
+
+    let file = std::fs::File::create(&heatmap.image_path)?;
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), raster.width, raster.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+    Ok(())
+}
+
+fn interpolate_color(fraction: f64) -> [u8; 3] {
+    let position = fraction * (COLOR_STOPS.len() - 1) as f64;
+    let stop_i = (position.floor() as usize).min(COLOR_STOPS.len() - 2);
+    let factor = position - stop_i as f64;
+    let (from, to) = (COLOR_STOPS[stop_i], COLOR_STOPS[stop_i + 1]);
+    std::array::from_fn(|i| (from[i] as f64 + (to[i] as f64 - from[i] as f64) * factor) as u8)
+}
+
+/// Writes the raw grid, which has the following layout with all numbers in little endian:
+/// - magic `TAGR`, format version, width and height as u32
+/// - minimum longitude, minimum latitude, maximum longitude and maximum latitude as f64
+/// - seconds per unit of the values as u32
+/// - travel times as u16 per pixel, row by row from the north; unreachable pixels are
+///   `u16::MAX`
+fn write_grid(raster: &Raster, travel_times: &[Option<u32>], heatmap: &HeatmapArgs) -> Result<()> {
+    let mut buffer = Vec::with_capacity(52 + travel_times.len() * 2);
+    buffer.extend_from_slice(GRID_MAGIC);
+    for value in [GRID_VERSION, raster.width, raster.height] {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [
+        raster.min_longitude,
+        raster.min_latitude,
+        raster.max_longitude,
+        raster.max_latitude,
+    ] {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    buffer.extend_from_slice(&GRID_SECONDS_PER_UNIT.to_le_bytes());
+    for travel_time in travel_times {
+        let value = match travel_time {
+            Some(travel_time) => (travel_time.div_ceil(GRID_SECONDS_PER_UNIT))
+                .min(GRID_UNREACHABLE as u32 - 1) as u16,
+            None => GRID_UNREACHABLE,
+        };
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    let mut file = std::fs::File::create(&heatmap.grid_path)?;
+    file.write_all(&buffer)?;
+    Ok(())
+}
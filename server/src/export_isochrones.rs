@@ -0,0 +1,136 @@
+use anyhow::Result;
+use std::{io::Write, path::Path};
+
+use crate::{
+    connection_filter::ConnectionFilterArgs,
+    find_optimal_paths::{self, StationState},
+    geojson, gtfs_rkyv, prepare_direct_connections_rkyv, prepare_gtfs_as_rkyv,
+    walking::{self, WalkingArgs},
+};
+
+/// Number of segments used to approximate the walking circle around a station.
+const CIRCLE_SEGMENTS_NUM: usize = 32;
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct IsochroneProperties {
+    minutes: u32,
+}
+
+pub struct Isochrone {
+    pub minutes: u32,
+    pub area: geo::MultiPolygon<f64>,
+}
+
+pub async fn export_isochrones(
+    gtfs_folder_path: &Path,
+    start_station_names: &[String],
+    band_minutes: &[u32],
+    output_path: &Path,
+    walking: &WalkingArgs,
+    connection_filter: &ConnectionFilterArgs,
+) -> Result<()> {
+    let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
+    let all_connections_rkyv =
+        prepare_direct_connections_rkyv::load_direct_connections_rkyv(gtfs_folder_path).await?;
+    let connection_filter = connection_filter.to_filter(&gtfs_rkyv)?;
+
+    let start_station_names: Vec<&str> = start_station_names
+        .iter()
+        .map(|name| name.as_str())
+        .collect();
+    let start_station_indices = find_optimal_paths::find_station_indices_by_names(
+        &gtfs_rkyv,
+        &all_connections_rkyv,
+        &start_station_names,
+    );
+    if start_station_indices.is_empty() {
+        anyhow::bail!("None of the given station names was found");
+    }
+
+    let station_states = find_optimal_paths::find_earliest_arrivals(
+        &all_connections_rkyv.stations,
+        &start_station_indices,
+        &connection_filter,
+    );
+
+    let isochrones = compute_isochrones(
+        &gtfs_rkyv,
+        &all_connections_rkyv,
+        &station_states,
+        band_minutes,
+        walking,
+    );
+
+    let result = geojson::FeatureCollection {
+        features: isochrones
+            .iter()
+            .map(|isochrone| geojson::Feature {
+                geometry: (&isochrone.area).into(),
+                properties: IsochroneProperties {
+                    minutes: isochrone.minutes,
+                },
+            })
+            .collect(),
+    };
+    let mut file = std::fs::File::create(output_path)?;
+    file.write_all(serde_json::to_string(&result)?.as_bytes())?;
+    Ok(())
+}
+
+/// Computes the area that can be reached within each time band. The area of a band is the
+/// union of circles around all stations reached within the band, where the radius is the
+/// distance that can be walked in the remaining time.
+pub fn compute_isochrones(
+    gtfs_rkyv: &gtfs_rkyv::ArchivedGtfsData,
+    all_connections_rkyv: &prepare_direct_connections_rkyv::ArchivedAllConnections,
+    station_states: &[StationState],
+    band_minutes: &[u32],
+    walking: &WalkingArgs,
+) -> Vec<Isochrone> {
+    let mut isochrones = vec![];
+    for minutes in band_minutes {
+        let band_seconds = minutes * 60;
+        let mut circles = vec![];
+        for (station, station_state) in all_connections_rkyv.stations.iter().zip(station_states) {
+            let Some(earliest_arrival) = station_state.earliest_arrival else {
+                continue;
+            };
+            if earliest_arrival >= band_seconds {
+                continue;
+            }
+            let stop = &gtfs_rkyv.stops[station.main_stop_i.to_native() as usize];
+            let (Some(latitude), Some(longitude)) =
+                (stop.latitude.as_ref(), stop.longitude.as_ref())
+            else {
+                continue;
+            };
+            let radius = walking.walking_distance(band_seconds - earliest_arrival);
+            circles.push(circle_polygon(
+                longitude.to_native(),
+                latitude.to_native(),
+                radius,
+            ));
+        }
+        isochrones.push(Isochrone {
+            minutes: *minutes,
+            area: geo::unary_union(&circles),
+        });
+    }
+    isochrones
+}
+
+/// Approximates a circle with the given radius in meters around a location.
+fn circle_polygon(longitude: f64, latitude: f64, radius: f64) -> geo::Polygon<f64> {
+    let radius_longitude = radius / walking::meters_per_degree_longitude(latitude);
+    let radius_latitude = radius / walking::METERS_PER_DEGREE_LATITUDE;
+    let points: Vec<geo::Coord<f64>> = (0..CIRCLE_SEGMENTS_NUM)
+        .map(|i| {
+            let angle = i as f64 / CIRCLE_SEGMENTS_NUM as f64 * std::f64::consts::TAU;
+            geo::coord! {
+                x: longitude + radius_longitude * angle.cos(),
+                y: latitude + radius_latitude * angle.sin(),
+            }
+        })
+        .collect();
+    geo::Polygon::new(geo::LineString::new(points), vec![])
+}
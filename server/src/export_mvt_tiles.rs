@@ -0,0 +1,239 @@
+use anyhow::Result;
+use indicatif::ProgressIterator;
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    connection_filter::ConnectionFilterArgs,
+    export_isochrones::{self, Isochrone},
+    export_station_tiles::{self, TileStation},
+    find_optimal_paths,
+    mvt::{self, MvtLayer, MvtValue},
+    prepare_direct_connections_rkyv, prepare_gtfs_as_rkyv,
+    walking::WalkingArgs,
+    web_mercator,
+};
+
+/// Polygons are clipped slightly outside of the tile so that no seams are visible.
+const CLIP_BUFFER: f64 = 64.0;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct MvtTilesArgs {
+    /// Directory that the `z/x/y.mvt` tiles are written to. It must be empty or not exist yet.
+    #[arg(long)]
+    pub output_dir: String,
+    #[arg(long, default_value_t = 4)]
+    pub min_zoom: u8,
+    #[arg(
+        long,
+        default_value_t = 14,
+        value_parser = clap::value_parser!(u8).range(..=web_mercator::MAX_ZOOM as i64),
+    )]
+    pub max_zoom: u8,
+    /// Below the maximum zoom level, only the most important stations are kept per tile.
+    #[arg(long, default_value_t = 500)]
+    pub max_stations_per_tile: usize,
+    /// Upper bounds of the isochrone time bands in minutes.
+    #[arg(long = "band", default_values_t = [15, 30, 45, 60])]
+    pub band_minutes: Vec<u32>,
+}
+
+/// Polygon of an isochrone with its rings projected into the Web Mercator unit square.
+struct ProjectedPolygon {
+    minutes: u32,
+    rings: Vec<Vec<(f64, f64)>>,
+    min: (f64, f64),
+    max: (f64, f64),
+}
+
+#[derive(Default)]
+struct TileContent<'a> {
+    stations: Vec<&'a TileStation>,
+    polygons: Vec<&'a ProjectedPolygon>,
+}
+
+/// Pre-renders Mapbox Vector Tiles with a `stations` layer containing the travel time to every
+/// station and an `isochrones` layer with the reachable area per time band.
+pub async fn export_mvt_tiles(
+    gtfs_folder_path: &Path,
+    start_station_names: &[String],
+    tiles: &MvtTilesArgs,
+    walking: &WalkingArgs,
+    connection_filter: &ConnectionFilterArgs,
+) -> Result<()> {
+    if tiles.min_zoom > tiles.max_zoom {
+        anyhow::bail!("The minimum zoom must not be larger than the maximum zoom");
+    }
+
+    let style = indicatif::ProgressStyle::with_template(
+        "[{elapsed_precise}] {bar:40.cyan/blue} {human_pos:>7}/{human_len:7} {msg}",
+    )
+    .unwrap();
+
+    let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
+    let all_connections_rkyv =
+        prepare_direct_connections_rkyv::load_direct_connections_rkyv(gtfs_folder_path).await?;
+    let connection_filter = connection_filter.to_filter(&gtfs_rkyv)?;
+
+    let start_station_names: Vec<&str> = start_station_names
+        .iter()
+        .map(|name| name.as_str())
+        .collect();
+    let start_station_indices = find_optimal_paths::find_station_indices_by_names(
+        &gtfs_rkyv,
+        &all_connections_rkyv,
+        &start_station_names,
+    );
+    if start_station_indices.is_empty() {
+        anyhow::bail!("None of the given station names was found");
+    }
+    let station_states = find_optimal_paths::find_earliest_arrivals(
+        &all_connections_rkyv.stations,
+        &start_station_indices,
+        &connection_filter,
+    );
+
+    let tile_stations = export_station_tiles::collect_tile_stations(
+        &gtfs_rkyv,
+        &all_connections_rkyv,
+        Some(&station_states),
+    );
+    let isochrones = export_isochrones::compute_isochrones(
+        &gtfs_rkyv,
+        &all_connections_rkyv,
+        &station_states,
+        &tiles.band_minutes,
+        walking,
+    );
+    let polygons = project_isochrones(&isochrones);
+
+    let output_dir = Path::new(&tiles.output_dir);
+    export_station_tiles::create_empty_output_dir(output_dir)?;
+    for zoom in (tiles.min_zoom..=tiles.max_zoom)
+        .progress_with_style(style)
+        .with_message("Write tiles.")
+        .with_finish(indicatif::ProgressFinish::AndLeave)
+    {
+        let tiles_num = 1u32 << zoom;
+        let mut content_by_tile: HashMap<(u32, u32), TileContent> = HashMap::new();
+        for tile_station in &tile_stations {
+            let station = &tile_station.station;
+            let tile = web_mercator::tile_for_location(station.longitude, station.latitude, zoom);
+            let content = content_by_tile.entry(tile).or_default();
+            if zoom < tiles.max_zoom && content.stations.len() >= tiles.max_stations_per_tile {
+                continue;
+            }
+            content.stations.push(tile_station);
+        }
+        for polygon in &polygons {
+            let to_tile_i = |value: f64| ((value * tiles_num as f64) as u32).min(tiles_num - 1);
+            for x in to_tile_i(polygon.min.0)..=to_tile_i(polygon.max.0) {
+                for y in to_tile_i(polygon.min.1)..=to_tile_i(polygon.max.1) {
+                    content_by_tile
+                        .entry((x, y))
+                        .or_default()
+                        .polygons
+                        .push(polygon);
+                }
+            }
+        }
+
+        for ((x, y), content) in content_by_tile {
+            let tile_data = render_tile(zoom, x, y, &content);
+            if tile_data.is_empty() {
+                continue;
+            }
+            let tile_dir = output_dir.join(zoom.to_string()).join(x.to_string());
+            std::fs::create_dir_all(&tile_dir)?;
+            std::fs::write(tile_dir.join(format!("{}.mvt", y)), tile_data)?;
+        }
+    }
+    Ok(())
+}
+
+fn project_isochrones(isochrones: &[Isochrone]) -> Vec<ProjectedPolygon> {
+    let mut polygons = vec![];
+    for isochrone in isochrones {
+        for polygon in isochrone.area.iter() {
+            let rings: Vec<Vec<(f64, f64)>> = std::iter::once(polygon.exterior())
+                .chain(polygon.interiors())
+                .map(|ring| {
+                    let mut points: Vec<(f64, f64)> = ring
+                        .coords()
+                        .map(|coord| web_mercator::location_to_unit_square(coord.x, coord.y))
+                        .collect();
+                    // Rings in vector tiles are closed implicitly.
+                    if points.len() > 1 && points.first() == points.last() {
+                        points.pop();
+                    }
+                    points
+                })
+                .collect();
+            let mut min = (f64::MAX, f64::MAX);
+            let mut max = (f64::MIN, f64::MIN);
+            for point in &rings[0] {
+                min = (min.0.min(point.0), min.1.min(point.1));
+                max = (max.0.max(point.0), max.1.max(point.1));
+            }
+            polygons.push(ProjectedPolygon {
+                minutes: isochrone.minutes,
+                rings,
+                min,
+                max,
+            });
+        }
+    }
+    polygons
+}
+
+fn render_tile(zoom: u8, x: u32, y: u32, content: &TileContent) -> Vec<u8> {
+    let extent = mvt::DEFAULT_EXTENT;
+    let tiles_num = (1u32 << zoom) as f64;
+    let to_tile_space = |point: (f64, f64)| {
+        (
+            (point.0 * tiles_num - x as f64) * extent as f64,
+            (point.1 * tiles_num - y as f64) * extent as f64,
+        )
+    };
+
+    let mut stations_layer = MvtLayer::new("stations", extent);
+    for tile_station in &content.stations {
+        let station = &tile_station.station;
+        let point = to_tile_space(web_mercator::location_to_unit_square(
+            station.longitude,
+            station.latitude,
+        ));
+        let mut properties = vec![
+            ("id", MvtValue::String(station.id.clone())),
+            ("name", MvtValue::String(station.name.clone())),
+        ];
+        if let Some(time) = station.time {
+            properties.push(("time", MvtValue::UInt(time as u64)));
+        }
+        stations_layer.add_point(
+            (point.0.round() as i32, point.1.round() as i32),
+            &properties,
+        );
+    }
+
+    let mut isochrones_layer = MvtLayer::new("isochrones", extent);
+    for polygon in &content.polygons {
+        let rings: Vec<Vec<(i32, i32)>> = polygon
+            .rings
+            .iter()
+            .map(|ring| {
+                let ring: Vec<(f64, f64)> =
+                    ring.iter().map(|point| to_tile_space(*point)).collect();
+                mvt::clip_ring(&ring, -CLIP_BUFFER, extent as f64 + CLIP_BUFFER)
+                    .into_iter()
+                    .map(|point| (point.0.round() as i32, point.1.round() as i32))
+                    .collect()
+            })
+            .collect();
+        isochrones_layer.add_polygon(
+            &rings,
+            &[("minutes", MvtValue::UInt(polygon.minutes as u64))],
+        );
+    }
+
+    mvt::encode_tile(&[stations_layer, isochrones_layer])
+}
@@ -0,0 +1,313 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Write,
+    path::Path,
+};
+
+use crate::{
+    compression::{self, Compression},
+    connection_filter::TransportMode,
+    geojson, prepare_gtfs_as_rkyv,
+    shapes::{self, ShapeStop},
+};
+
+/// Identifies files in the binary route line format.
+const BINARY_MAGIC: &[u8; 4] = b"TARL";
+const BINARY_VERSION: u32 = 3;
+/// Written instead of the color of routes without one.
+const BINARY_NO_COLOR: u32 = u32::MAX;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum RouteLinesFormat {
+    /// FeatureCollection with one LineString per route pattern.
+    Geojson,
+    /// Packed arrays that can be uploaded to WebGL buffers directly, see `write_binary`.
+    Binary,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RouteLinesArgs {
+    #[arg(long)]
+    pub output_path: String,
+    #[arg(long, value_enum, default_value_t = RouteLinesFormat::Geojson)]
+    pub format: RouteLinesFormat,
+    /// Compression applied to the entire output file.
+    #[arg(long, value_enum, default_value_t = Compression::None)]
+    pub compression: Compression,
+    /// Only count trips running on this day, in the format `YYYY-MM-DD`. Patterns without
+    /// trips on that day are left out.
+    #[arg(long)]
+    pub date: Option<NaiveDate>,
+}
+
+/// Trips of a route that stop at the same stops in the same order.
+struct RoutePattern {
+    route_i: usize,
+    /// `[longitude, latitude]` pairs along the shape of the first trip that has one.
+    coordinates: Vec<[f64; 2]>,
+    follows_shape: bool,
+    trips_num: u32,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct RoutePatternProperties<'a> {
+    route_id: &'a str,
+    short_name: Option<&'a str>,
+    long_name: Option<&'a str>,
+    mode: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<&'a str>,
+    /// `#RRGGBB`, absent if the feed doesn't give one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<String>,
+    /// `#RRGGBB`, absent if the feed doesn't give one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text_color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort_order: Option<u32>,
+    trips_num: u32,
+}
+
+/// Exports the transit network as one line per route pattern, e.g. to draw it below the
+/// stations on a map.
+pub async fn export_route_lines(gtfs_folder_path: &Path, args: &RouteLinesArgs) -> Result<()> {
+    let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
+
+    let stop_index_by_id: HashMap<&str, usize> = gtfs_rkyv
+        .stops
+        .iter()
+        .enumerate()
+        .map(|(stop_i, stop)| (stop.id.as_str(), stop_i))
+        .collect();
+    let route_index_by_id: HashMap<&str, usize> = gtfs_rkyv
+        .routes
+        .iter()
+        .enumerate()
+        .map(|(route_i, route)| (route.id.as_str(), route_i))
+        .collect();
+    let running_service_ids: Option<HashSet<&str>> = args.date.map(|date| {
+        gtfs_rkyv
+            .services
+            .iter()
+            .filter(|service| service.runs_on(date))
+            .map(|service| service.id.as_str())
+            .collect()
+    });
+
+    // Keyed by route and stop indices so that the output order does not depend on hashing.
+    let mut patterns: BTreeMap<(usize, Vec<u32>), RoutePattern> = BTreeMap::new();
+    for (trip_i, trip) in gtfs_rkyv.trips.iter().enumerate() {
+        if let Some(running_service_ids) = &running_service_ids {
+            if !running_service_ids.contains(trip.service_id.as_str()) {
+                continue;
+            }
+        }
+        let Some(route_i) = route_index_by_id.get(trip.route_id.as_str()) else {
+            continue;
+        };
+        let stop_time_range = gtfs_rkyv.stop_times.trip_range(trip_i);
+        if stop_time_range.len() < 2 {
+            continue;
+        }
+        let stop_indices: Vec<u32> = gtfs_rkyv.stop_times.stop_indices[stop_time_range.clone()]
+            .iter()
+            .map(|stop_i| stop_i.to_native())
+            .collect();
+        let shape = trip
+            .shape_id
+            .as_ref()
+            .and_then(|shape_id| gtfs_rkyv.shape(shape_id.as_str()));
+
+        let pattern = patterns
+            .entry((*route_i, stop_indices))
+            .or_insert_with(|| RoutePattern {
+                route_i: *route_i,
+                coordinates: vec![],
+                follows_shape: false,
+                trips_num: 0,
+            });
+        pattern.trips_num += 1;
+        if pattern.coordinates.is_empty() || (!pattern.follows_shape && shape.is_some()) {
+            let shape_stops: Vec<ShapeStop> = stop_time_range
+                .clone()
+                .map(|stop_time_i| {
+                    ShapeStop::new(
+                        &gtfs_rkyv.stops,
+                        &stop_index_by_id,
+                        &gtfs_rkyv.stop_times,
+                        stop_time_i,
+                    )
+                })
+                .collect();
+            let leg_geometries = shapes::trip_leg_geometries(shape, &shape_stops);
+            let follows_shape = leg_geometries.iter().any(|leg| leg.follows_shape);
+            if pattern.coordinates.is_empty() || follows_shape {
+                pattern.coordinates = join_legs(leg_geometries);
+                pattern.follows_shape = follows_shape;
+            }
+        }
+    }
+
+    let mut patterns: Vec<RoutePattern> = patterns
+        .into_values()
+        .filter(|pattern| pattern.coordinates.len() >= 2)
+        .collect();
+    // Lines are drawn in this order, routes without a sort order come last.
+    patterns.sort_by_key(|pattern| {
+        gtfs_rkyv.routes[pattern.route_i]
+            .sort_order
+            .as_ref()
+            .map_or(u32::MAX, |sort_order| sort_order.to_native())
+    });
+    log::info!("Exporting {} route patterns", patterns.len());
+
+    let mut buffer = vec![];
+    match args.format {
+        RouteLinesFormat::Geojson => {
+            let feature_collection = geojson::FeatureCollection {
+                features: patterns
+                    .iter()
+                    .map(|pattern| {
+                        let route = &gtfs_rkyv.routes[pattern.route_i];
+                        geojson::Feature {
+                            geometry: geojson::Geometry::LineString(pattern.coordinates.clone()),
+                            properties: RoutePatternProperties {
+                                route_id: route.id.as_str(),
+                                short_name: route.short_name.as_ref().map(|name| name.as_str()),
+                                long_name: route.long_name.as_ref().map(|name| name.as_str()),
+                                mode: format!("{:?}", TransportMode::from_route(route)),
+                                description: route
+                                    .description
+                                    .as_ref()
+                                    .map(|description| description.as_str()),
+                                url: route.url.as_ref().map(|url| url.as_str()),
+                                color: route
+                                    .color
+                                    .as_ref()
+                                    .map(|color| format!("#{:06X}", color.to_native())),
+                                text_color: route
+                                    .text_color
+                                    .as_ref()
+                                    .map(|color| format!("#{:06X}", color.to_native())),
+                                sort_order: route
+                                    .sort_order
+                                    .as_ref()
+                                    .map(|sort_order| sort_order.to_native()),
+                                trips_num: pattern.trips_num,
+                            },
+                        }
+                    })
+                    .collect(),
+            };
+            serde_json::to_writer(&mut buffer, &feature_collection)?;
+        }
+        RouteLinesFormat::Binary => {
+            write_binary(&gtfs_rkyv, &patterns, &mut buffer)?;
+        }
+    }
+    compression::write_compressed_file(Path::new(&args.output_path), &buffer, args.compression)
+}
+
+/// Concatenates the legs of a trip, leaving out the point shared by consecutive legs.
+fn join_legs(leg_geometries: Vec<shapes::LegGeometry>) -> Vec<[f64; 2]> {
+    let mut coordinates: Vec<[f64; 2]> = vec![];
+    for leg in leg_geometries {
+        let skip_num = match (coordinates.last(), leg.coordinates.first()) {
+            (Some(last), Some(first)) if last == first => 1,
+            _ => 0,
+        };
+        coordinates.extend(leg.coordinates.into_iter().skip(skip_num));
+    }
+    coordinates
+}
+
+/// Writes route patterns, ordered by the route sort order, in a compact little-endian binary layout:
+/// - Header: magic `TARL`, version (u32), patterns num (u32), points num (u32).
+/// - Point offsets: `patterns_num + 1` u32 indices into the positions.
+/// - Positions: longitude and latitude (f32) for each point.
+/// - Colors: one u32 `0x00RRGGBB` per pattern, `0xFFFFFFFF` if the route has none.
+/// - Text colors: one u32 `0x00RRGGBB` per pattern, `0xFFFFFFFF` if the route has none.
+/// - Modes: one u32 per pattern, the index of the `TransportMode`.
+/// - Trips: one u32 per pattern with the number of trips.
+/// - Names: `patterns_num + 1` u32 byte offsets followed by the UTF-8 data of the route short
+///   names, or the long names for routes without a short name.
+///
+/// All sections start at multiples of four bytes so that they can be viewed as typed arrays.
+fn write_binary(
+    gtfs_rkyv: &crate::gtfs_rkyv::ArchivedGtfsData,
+    patterns: &[RoutePattern],
+    writer: &mut impl Write,
+) -> Result<()> {
+    let points_num: usize = patterns
+        .iter()
+        .map(|pattern| pattern.coordinates.len())
+        .sum();
+    writer.write_all(BINARY_MAGIC)?;
+    writer.write_all(&BINARY_VERSION.to_le_bytes())?;
+    writer.write_all(&(patterns.len() as u32).to_le_bytes())?;
+    writer.write_all(&(points_num as u32).to_le_bytes())?;
+
+    let mut offset = 0u32;
+    writer.write_all(&offset.to_le_bytes())?;
+    for pattern in patterns {
+        offset += pattern.coordinates.len() as u32;
+        writer.write_all(&offset.to_le_bytes())?;
+    }
+    for pattern in patterns {
+        for [longitude, latitude] in &pattern.coordinates {
+            writer.write_all(&(*longitude as f32).to_le_bytes())?;
+            writer.write_all(&(*latitude as f32).to_le_bytes())?;
+        }
+    }
+    let routes: Vec<_> = patterns
+        .iter()
+        .map(|pattern| &gtfs_rkyv.routes[pattern.route_i])
+        .collect();
+    for route in &routes {
+        let color = route
+            .color
+            .as_ref()
+            .map_or(BINARY_NO_COLOR, |color| color.to_native());
+        writer.write_all(&color.to_le_bytes())?;
+    }
+    for route in &routes {
+        let text_color = route
+            .text_color
+            .as_ref()
+            .map_or(BINARY_NO_COLOR, |color| color.to_native());
+        writer.write_all(&text_color.to_le_bytes())?;
+    }
+    for route in &routes {
+        let mode = TransportMode::from_route(route) as u32;
+        writer.write_all(&mode.to_le_bytes())?;
+    }
+    for pattern in patterns {
+        writer.write_all(&pattern.trips_num.to_le_bytes())?;
+    }
+
+    let names: Vec<&str> = routes
+        .iter()
+        .map(|route| {
+            route
+                .short_name
+                .as_ref()
+                .or(route.long_name.as_ref())
+                .map(|name| name.as_str())
+                .unwrap_or("")
+        })
+        .collect();
+    let mut offset = 0u32;
+    writer.write_all(&offset.to_le_bytes())?;
+    for name in &names {
+        offset += name.len() as u32;
+        writer.write_all(&offset.to_le_bytes())?;
+    }
+    for name in &names {
+        writer.write_all(name.as_bytes())?;
+    }
+    Ok(())
+}
@@ -1,37 +1,38 @@
 use anyhow::Result;
-use std::{io::Write, path::Path};
+use std::path::Path;
 
-use crate::prepare_gtfs_as_rkyv;
+use crate::{
+    prepare_gtfs_as_rkyv,
+    station_output::{self, OutputStation, StationOutputArgs},
+};
 
-#[derive(Debug, Clone, serde::Serialize)]
-struct StationLocations {
-    stations: Vec<StationInfo>,
-}
-
-#[derive(Debug, Clone, serde::Serialize)]
-struct StationInfo {
-    name: String,
-    latitude: f64,
-    longitude: f64,
-}
-
-pub async fn export_station_locations(gtfs_folder_path: &Path, output_path: &Path) -> Result<()> {
+pub async fn export_station_locations(
+    gtfs_folder_path: &Path,
+    output: &StationOutputArgs,
+) -> Result<()> {
     let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
 
-    let mut result = StationLocations { stations: vec![] };
+    let mut stations = vec![];
 
     for stop in gtfs_rkyv.stops.iter() {
-        if let Some(name) = stop.name.as_ref() {
-            if name.contains("Hennigsdorf") && stop.parent_station_id.is_none() {
-                result.stations.push(StationInfo {
-                    name: name.as_str().to_string(),
-                    latitude: stop.latitude.unwrap().to_native(),
-                    longitude: stop.longitude.unwrap().to_native(),
+        if let (Some(name), Some(latitude), Some(longitude)) = (
+            stop.name.as_ref(),
+            stop.latitude.as_ref(),
+            stop.longitude.as_ref(),
+        ) {
+            if name.contains("Hennigsdorf")
+                && stop.parent_station_id.is_none()
+                && stop.location_type.can_be_station()
+            {
+                stations.push(OutputStation {
+                    id: stop.id.to_string(),
+                    name: name.to_string(),
+                    latitude: latitude.to_native(),
+                    longitude: longitude.to_native(),
+                    time: None,
                 });
             }
         }
     }
-    let mut file = std::fs::File::create(output_path)?;
-    file.write_all(serde_json::to_string_pretty(&result)?.as_bytes())?;
-    Ok(())
+    station_output::write_stations(&stations, output)
 }
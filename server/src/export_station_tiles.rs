@@ -0,0 +1,200 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use indicatif::ProgressIterator;
+use std::{collections::HashMap, io::Write, path::Path};
+
+use crate::{
+    compression::{self, Compression},
+    connection_filter::ConnectionFilterArgs,
+    find_optimal_paths::{self, StationState},
+    gtfs_rkyv, prepare_direct_connections_rkyv, prepare_gtfs_as_rkyv,
+    station_output::{self, OutputStation, StationOutputFormat},
+    web_mercator,
+};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct StationTilesArgs {
+    /// Directory that the `z/x/y` tiles are written to. It must be empty or not exist yet.
+    #[arg(long)]
+    pub output_dir: String,
+    #[arg(long, default_value_t = 4)]
+    pub min_zoom: u8,
+    #[arg(
+        long,
+        default_value_t = 14,
+        value_parser = clap::value_parser!(u8).range(..=web_mercator::MAX_ZOOM as i64),
+    )]
+    pub max_zoom: u8,
+    /// Below the maximum zoom level, only the most important stations are kept per tile.
+    #[arg(long, default_value_t = 500)]
+    pub max_stations_per_tile: usize,
+    #[arg(long, value_enum, default_value_t = StationOutputFormat::Binary)]
+    pub format: StationOutputFormat,
+    #[arg(long, value_enum, default_value_t = Compression::None)]
+    pub compression: Compression,
+    #[arg(long)]
+    pub without_names: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct TilesMetadata {
+    min_zoom: u8,
+    max_zoom: u8,
+    format: String,
+    /// The tiles keep the extension of their format, so they have to be decoded (or served
+    /// with the matching `Content-Encoding`) according to this.
+    compression: String,
+    tiles_num: usize,
+}
+
+pub struct TileStation {
+    pub station: OutputStation,
+    pub importance: usize,
+}
+
+/// Writes a static tile pyramid of the stations that can be uploaded to object storage as is.
+/// If start stations are given, the tiles also contain the travel time from them.
+pub async fn export_station_tiles(
+    gtfs_folder_path: &Path,
+    start_station_names: &[String],
+    tiles: &StationTilesArgs,
+    connection_filter: &ConnectionFilterArgs,
+) -> Result<()> {
+    if tiles.min_zoom > tiles.max_zoom {
+        anyhow::bail!("The minimum zoom must not be larger than the maximum zoom");
+    }
+
+    let style = indicatif::ProgressStyle::with_template(
+        "[{elapsed_precise}] {bar:40.cyan/blue} {human_pos:>7}/{human_len:7} {msg}",
+    )
+    .unwrap();
+
+    let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
+    let all_connections_rkyv =
+        prepare_direct_connections_rkyv::load_direct_connections_rkyv(gtfs_folder_path).await?;
+    let connection_filter = connection_filter.to_filter(&gtfs_rkyv)?;
+
+    let station_states = if start_station_names.is_empty() {
+        None
+    } else {
+        let start_station_names: Vec<&str> = start_station_names
+            .iter()
+            .map(|name| name.as_str())
+            .collect();
+        let start_station_indices = find_optimal_paths::find_station_indices_by_names(
+            &gtfs_rkyv,
+            &all_connections_rkyv,
+            &start_station_names,
+        );
+        if start_station_indices.is_empty() {
+            anyhow::bail!("None of the given station names was found");
+        }
+        Some(find_optimal_paths::find_earliest_arrivals(
+            &all_connections_rkyv.stations,
+            &start_station_indices,
+            &connection_filter,
+        ))
+    };
+
+    let tile_stations =
+        collect_tile_stations(&gtfs_rkyv, &all_connections_rkyv, station_states.as_deref());
+
+    let output_dir = Path::new(&tiles.output_dir);
+    create_empty_output_dir(output_dir)?;
+    let extension = tiles.format.file_extension();
+    let mut tiles_num = 0;
+    for zoom in (tiles.min_zoom..=tiles.max_zoom)
+        .progress_with_style(style)
+        .with_message("Write tiles.")
+        .with_finish(indicatif::ProgressFinish::AndLeave)
+    {
+        let mut stations_by_tile: HashMap<(u32, u32), Vec<OutputStation>> = HashMap::new();
+        for tile_station in &tile_stations {
+            let station = &tile_station.station;
+            let tile = web_mercator::tile_for_location(station.longitude, station.latitude, zoom);
+            let stations_in_tile = stations_by_tile.entry(tile).or_default();
+            if zoom < tiles.max_zoom && stations_in_tile.len() >= tiles.max_stations_per_tile {
+                continue;
+            }
+            stations_in_tile.push(station.clone());
+        }
+        for ((x, y), stations) in stations_by_tile {
+            let tile_dir = output_dir.join(zoom.to_string()).join(x.to_string());
+            std::fs::create_dir_all(&tile_dir)?;
+            let buffer =
+                station_output::encode_stations(&stations, tiles.format, !tiles.without_names)?;
+            compression::write_compressed_file(
+                &tile_dir.join(format!("{}.{}", y, extension)),
+                &buffer,
+                tiles.compression,
+            )?;
+            tiles_num += 1;
+        }
+    }
+
+    let metadata = TilesMetadata {
+        min_zoom: tiles.min_zoom,
+        max_zoom: tiles.max_zoom,
+        format: extension.to_string(),
+        compression: tiles
+            .compression
+            .to_possible_value()
+            .expect("no skipped compression values")
+            .get_name()
+            .to_string(),
+        tiles_num,
+    };
+    let mut file = std::fs::File::create(output_dir.join("metadata.json"))?;
+    file.write_all(serde_json::to_string_pretty(&metadata)?.as_bytes())?;
+    Ok(())
+}
+
+/// Creates the directory that tiles are written to. Writing into a non-empty directory is
+/// refused, since tiles of an earlier export that are not overwritten would remain.
+pub fn create_empty_output_dir(output_dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(output_dir)?;
+    if std::fs::read_dir(output_dir)?.next().is_some() {
+        anyhow::bail!(
+            "The output directory {:?} is not empty, remove it or choose another one",
+            output_dir
+        );
+    }
+    Ok(())
+}
+
+/// Collects all stations with a location, sorted so that the most important ones come first.
+/// Stations with more connections are considered more important and are kept at lower zoom
+/// levels.
+pub fn collect_tile_stations(
+    gtfs_rkyv: &gtfs_rkyv::ArchivedGtfsData,
+    all_connections_rkyv: &prepare_direct_connections_rkyv::ArchivedAllConnections,
+    station_states: Option<&[StationState]>,
+) -> Vec<TileStation> {
+    let mut tile_stations = vec![];
+    for (station_i, station) in all_connections_rkyv.stations.iter().enumerate() {
+        let stop = &gtfs_rkyv.stops[station.main_stop_i.to_native() as usize];
+        let (Some(name), Some(latitude), Some(longitude)) = (
+            stop.name.as_ref(),
+            stop.latitude.as_ref(),
+            stop.longitude.as_ref(),
+        ) else {
+            continue;
+        };
+        let importance = station.connections.len()
+            + all_connections_rkyv.reversed_stations[station_i]
+                .connections
+                .len();
+        tile_stations.push(TileStation {
+            station: OutputStation {
+                id: stop.id.to_string(),
+                name: name.to_string(),
+                latitude: latitude.to_native(),
+                longitude: longitude.to_native(),
+                time: station_states.and_then(|states| states[station_i].earliest_arrival),
+            },
+            importance,
+        });
+    }
+    tile_stations.sort_by_key(|tile_station| std::cmp::Reverse(tile_station.importance));
+    tile_stations
+}
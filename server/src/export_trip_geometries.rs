@@ -0,0 +1,135 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use std::{collections::HashMap, io::Write, path::Path};
+
+use crate::{
+    geojson,
+    gtfs_rkyv::ArchivedGtfsStop,
+    prepare_gtfs_as_rkyv,
+    shapes::{self, ShapeStop},
+    timezones,
+};
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct LegProperties {
+    trip_id: String,
+    route_id: String,
+    route_short_name: Option<String>,
+    /// `#RRGGBB`, absent if the route is unknown or has no color.
+    route_color: Option<String>,
+    route_text_color: Option<String>,
+    from_stop_id: String,
+    from_stop_name: Option<String>,
+    to_stop_id: String,
+    to_stop_name: Option<String>,
+    /// Seconds since `timezones::service_day_start`, i.e. noon minus 12 hours of the service
+    /// day, as in `stop_times.txt`.
+    departure_time: Option<u32>,
+    arrival_time: Option<u32>,
+    /// RFC 3339 timestamps in the timezone of the stop, only if a service date was given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    departure: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    arrival: Option<String>,
+    /// Whether the geometry follows the trip's shape or is a straight line between the stops.
+    follows_shape: bool,
+}
+
+/// Writes a GeoJSON FeatureCollection with one LineString per leg between consecutive stops
+/// of the given trips. With a service date, the times are also written as timestamps.
+pub async fn export_trip_geometries(
+    gtfs_folder_path: &Path,
+    trip_ids: &[String],
+    date: Option<NaiveDate>,
+    output_path: &Path,
+) -> Result<()> {
+    let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
+    let feed_timezone = match date {
+        Some(_) => Some(gtfs_rkyv.feed_timezone()?),
+        None => None,
+    };
+
+    let stop_index_by_id: HashMap<&str, usize> = gtfs_rkyv
+        .stops
+        .iter()
+        .enumerate()
+        .map(|(stop_i, stop)| (stop.id.as_str(), stop_i))
+        .collect();
+
+    let mut features = vec![];
+    for trip_id in trip_ids {
+        let Some(trip_i) = gtfs_rkyv
+            .trips
+            .iter()
+            .position(|trip| trip.id.as_str() == trip_id)
+        else {
+            anyhow::bail!("Unknown trip {:?}", trip_id);
+        };
+        let trip = &gtfs_rkyv.trips[trip_i];
+        let stop_times = &gtfs_rkyv.stop_times;
+        let stop_time_range = stop_times.trip_range(trip_i);
+
+        let route = gtfs_rkyv
+            .routes
+            .iter()
+            .find(|route| route.id == trip.route_id);
+        let shape = trip
+            .shape_id
+            .as_ref()
+            .and_then(|shape_id| gtfs_rkyv.shape(shape_id.as_str()));
+        let shape_stops: Vec<ShapeStop> = stop_time_range
+            .clone()
+            .map(|stop_time_i| {
+                ShapeStop::new(&gtfs_rkyv.stops, &stop_index_by_id, stop_times, stop_time_i)
+            })
+            .collect();
+        let leg_geometries = shapes::trip_leg_geometries(shape, &shape_stops);
+
+        let timestamp = |stop: &ArchivedGtfsStop, time: Option<u32>| {
+            let (date, feed_timezone) = (date?, feed_timezone?);
+            let instant = timezones::stop_time_to_utc(date, feed_timezone, time?);
+            let stop_timezone = timezones::stop_timezone(stop, feed_timezone);
+            Some(instant.with_timezone(&stop_timezone).to_rfc3339())
+        };
+        for (from_stop_time_i, leg_geometry) in stop_time_range.zip(leg_geometries) {
+            if leg_geometry.coordinates.is_empty() {
+                continue;
+            }
+            let to_stop_time_i = from_stop_time_i + 1;
+            let from_stop = &gtfs_rkyv.stops[stop_times.stop_i(from_stop_time_i)];
+            let to_stop = &gtfs_rkyv.stops[stop_times.stop_i(to_stop_time_i)];
+            let departure_time = stop_times.departure_time(from_stop_time_i);
+            let arrival_time = stop_times.arrival_time(to_stop_time_i);
+            features.push(geojson::Feature {
+                geometry: geojson::Geometry::LineString(leg_geometry.coordinates),
+                properties: LegProperties {
+                    trip_id: trip_id.clone(),
+                    route_id: trip.route_id.to_string(),
+                    route_short_name: route
+                        .and_then(|route| route.short_name.as_ref())
+                        .map(|name| name.to_string()),
+                    route_color: route
+                        .and_then(|route| route.color.as_ref())
+                        .map(|color| format!("#{:06X}", color.to_native())),
+                    route_text_color: route
+                        .and_then(|route| route.text_color.as_ref())
+                        .map(|color| format!("#{:06X}", color.to_native())),
+                    from_stop_id: from_stop.id.to_string(),
+                    from_stop_name: from_stop.name.as_ref().map(|name| name.to_string()),
+                    to_stop_id: to_stop.id.to_string(),
+                    to_stop_name: to_stop.name.as_ref().map(|name| name.to_string()),
+                    departure_time,
+                    arrival_time,
+                    departure: timestamp(from_stop, departure_time),
+                    arrival: timestamp(to_stop, arrival_time),
+                    follows_shape: leg_geometry.follows_shape,
+                },
+            });
+        }
+    }
+
+    let result = geojson::FeatureCollection { features };
+    let mut file = std::fs::File::create(output_path)?;
+    file.write_all(serde_json::to_string(&result)?.as_bytes())?;
+    Ok(())
+}
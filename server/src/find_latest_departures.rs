@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::{
+    connection_filter::ConnectionFilterArgs,
+    find_optimal_paths::{self, StationState},
+    pooled_chunked_vector::ChunkedVectorPool,
+    prepare_direct_connections_rkyv, prepare_gtfs_as_rkyv,
+    station_output::{self, OutputStation, StationOutputArgs},
+};
+
+/// Finds for every station the latest departure time that still allows reaching one of the
+/// target stations by the given arrival time. The search runs on the reversed connections.
+pub async fn find_latest_departures(
+    gtfs_folder_path: &Path,
+    target_station_names: &[String],
+    arrival_time: &str,
+    output: &StationOutputArgs,
+    connection_filter: &ConnectionFilterArgs,
+) -> Result<()> {
+    let arrival_time = parse_time_of_day(arrival_time)?;
+
+    let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
+    let all_connections_rkyv =
+        prepare_direct_connections_rkyv::load_direct_connections_rkyv(gtfs_folder_path).await?;
+    let connection_filter = connection_filter.to_filter(&gtfs_rkyv)?;
+
+    let target_station_names: Vec<&str> = target_station_names
+        .iter()
+        .map(|name| name.as_str())
+        .collect();
+    let target_station_indices = find_optimal_paths::find_station_indices_by_names(
+        &gtfs_rkyv,
+        &all_connections_rkyv,
+        &target_station_names,
+    );
+    if target_station_indices.is_empty() {
+        anyhow::bail!("None of the given station names was found");
+    }
+
+    let mut station_states = vec![
+        StationState {
+            earliest_arrival: None,
+        };
+        all_connections_rkyv.reversed_stations.len()
+    ];
+    let mut chunk_pool = ChunkedVectorPool::new();
+
+    // In the reversed graph, the "earliest arrival" is the shortest duration from a station
+    // to any of the targets.
+    find_optimal_paths::find_optimal_paths_with_time_buckets(
+        &all_connections_rkyv.reversed_stations,
+        &target_station_indices,
+        &mut station_states,
+        &mut chunk_pool,
+        &connection_filter,
+    );
+
+    let mut stations = vec![];
+    for (station, station_state) in all_connections_rkyv.stations.iter().zip(&station_states) {
+        let Some(duration) = station_state.earliest_arrival else {
+            continue;
+        };
+        if duration > arrival_time {
+            // Would have to depart on the previous day.
+            continue;
+        }
+        let stop = &gtfs_rkyv.stops[station.main_stop_i.to_native() as usize];
+        let (Some(name), Some(latitude), Some(longitude)) = (
+            stop.name.as_ref(),
+            stop.latitude.as_ref(),
+            stop.longitude.as_ref(),
+        ) else {
+            continue;
+        };
+        stations.push(OutputStation {
+            id: stop.id.to_string(),
+            name: name.to_string(),
+            time: Some(arrival_time - duration),
+            latitude: latitude.to_native(),
+            longitude: longitude.to_native(),
+        });
+    }
+
+    station_output::write_stations(&stations, output)
+}
+
+/// Parses a time like `09:00` or `09:00:00` into seconds since midnight. Like in GTFS, hours
+/// may be 24 or more for times after midnight.
+pub fn parse_time_of_day(time: &str) -> Result<u32> {
+    let parts: Vec<&str> = time.split(':').collect();
+    if !(2..=3).contains(&parts.len()) {
+        anyhow::bail!("Expected time in the format HH:MM[:SS], got {:?}", time);
+    }
+    let mut seconds: u32 = 0;
+    for (part_i, (part, factor)) in parts.iter().zip([3600, 60, 1]).enumerate() {
+        let value: u32 = part
+            .parse()
+            .with_context(|| format!("Invalid time {:?}", time))?;
+        if part_i > 0 && value >= 60 {
+            anyhow::bail!("Minutes and seconds must be below 60, got {:?}", time);
+        }
+        seconds = value
+            .checked_mul(factor)
+            .and_then(|part_seconds| seconds.checked_add(part_seconds))
+            .with_context(|| format!("Time {:?} is too large", time))?;
+    }
+    Ok(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_times_of_day() {
+        assert_eq!(parse_time_of_day("09:05").unwrap(), 9 * 3600 + 5 * 60);
+        assert_eq!(
+            parse_time_of_day("09:05:30").unwrap(),
+            9 * 3600 + 5 * 60 + 30
+        );
+        assert_eq!(parse_time_of_day("25:00").unwrap(), 25 * 3600);
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        for time in [
+            "9",
+            "09:00:00:00",
+            "09:",
+            "ab:00",
+            "-1:00",
+            "09:75",
+            "10:00:99",
+        ] {
+            assert!(parse_time_of_day(time).is_err(), "{}", time);
+        }
+    }
+
+    #[test]
+    fn rejects_times_that_overflow() {
+        assert!(parse_time_of_day("1193047:00").is_err());
+        assert!(parse_time_of_day("1193046:28:16").is_err());
+        assert_eq!(
+            parse_time_of_day("1193046:28:15").unwrap(),
+            1193046 * 3600 + 28 * 60 + 15
+        );
+    }
+}
@@ -1,50 +1,38 @@
-use std::{cmp::Reverse, collections::BinaryHeap, io::Write, path::Path};
+use std::{cmp::Reverse, collections::BinaryHeap, path::Path};
 
-use crate::pooled_chunked_vector::{ChunkedVector, ChunkedVectorPool};
+use crate::{
+    connection_filter::{ConnectionFilter, ConnectionFilterArgs},
+    pooled_chunked_vector::{ChunkedVector, ChunkedVectorPool},
+    station_output::{self, OutputStation, StationOutputArgs},
+};
 
 use anyhow::Result;
+use indicatif::ProgressIterator;
 
-use crate::{prepare_direct_connections_rkyv, prepare_gtfs_as_rkyv};
+use crate::{gtfs_rkyv, prepare_direct_connections_rkyv, prepare_gtfs_as_rkyv};
 
 #[derive(Debug, Clone)]
-struct StationState {
-    earliest_arrival: Option<u32>,
+pub struct StationState {
+    pub earliest_arrival: Option<u32>,
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
-struct OutputStationsWithTime {
-    stations: Vec<OutputStationWithTime>,
-}
-
-#[derive(Debug, Clone, serde::Serialize)]
-struct OutputStationWithTime {
-    name: String,
-    time: u32,
-    latitude: f64,
-    longitude: f64,
-}
-
-pub async fn find_optimal_paths(gtfs_folder_path: &Path) -> Result<()> {
+pub async fn find_optimal_paths(
+    gtfs_folder_path: &Path,
+    output: &StationOutputArgs,
+    connection_filter: &ConnectionFilterArgs,
+) -> Result<()> {
     let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
     let all_connections_rkyv =
         prepare_direct_connections_rkyv::load_direct_connections_rkyv(gtfs_folder_path).await?;
+    let connection_filter = connection_filter.to_filter(&gtfs_rkyv)?;
 
-    let mut start_station_indices = vec![];
-
-    let start_station_names = vec!["S Hennigsdorf Bhf", "AB-Schweinheim, Feldchenstr."];
-
-    for (i, station) in all_connections_rkyv.stations.iter().enumerate() {
-        let stop = &gtfs_rkyv.stops[station.main_stop_i.to_native() as usize];
-        if let Some(name) = stop.name.as_ref() {
-            if start_station_names.contains(&name.as_str()) {
-                start_station_indices.push(i as u32);
-            }
-        }
-    }
+    let start_station_names = ["S Hennigsdorf Bhf", "AB-Schweinheim, Feldchenstr."];
+    let start_station_indices =
+        find_station_indices_by_names(&gtfs_rkyv, &all_connections_rkyv, &start_station_names);
 
     let mut station_states = vec![
         StationState {
-            earliest_arrival: None
+            earliest_arrival: None,
         };
         all_connections_rkyv.stations.len()
     ];
@@ -57,144 +45,310 @@ pub async fn find_optimal_paths(gtfs_folder_path: &Path) -> Result<()> {
 
     for iteration_i in 0..iterations_num {
         // _find_optimal_paths_with_binary_heap(
-        //     &all_connections_rkyv,
+        //     &all_connections_rkyv.stations,
         //     &start_station_indices,
         //     &mut station_states,
+        //     &connection_filter,
         // );
         find_optimal_paths_with_time_buckets(
-            &all_connections_rkyv,
+            &all_connections_rkyv.stations,
             &start_station_indices,
             &mut station_states,
             &mut chunk_pool,
+            &connection_filter,
         );
 
         if iteration_i < iterations_num - 1 {
             // Reset for benchmarking reasons.
-            for item in &mut station_states {
-                *item = StationState {
-                    earliest_arrival: None,
-                };
-            }
+            station_states.fill(StationState {
+                earliest_arrival: None,
+            });
         }
     }
 
     println!("Took {:?}", start_instant.elapsed());
 
-    let mut result = OutputStationsWithTime { stations: vec![] };
+    let mut stations = collect_reached_stations(&gtfs_rkyv, &all_connections_rkyv, &station_states);
+    stations.retain(|station| {
+        (52.19..=53.12).contains(&station.latitude)
+            && (12.46..=14.0).contains(&station.longitude)
+            && station.name.contains("Hennigsdorf")
+    });
 
-    for station_i in 0..all_connections_rkyv.stations.len() {
-        let station = &all_connections_rkyv.stations[station_i as usize];
-        let station_state = &station_states[station_i as usize];
-        let stop = &gtfs_rkyv.stops[station.main_stop_i.to_native() as usize];
+    station_output::write_stations(&stations, output)?;
+
+    // println!(
+    //     "Station states: {:#?}",
+    //     station_states.iter().take(10000).collect::<Vec<_>>()
+    // );
+
+    Ok(())
+}
 
-        if stop.latitude.unwrap() > 53.12
-            || stop.latitude.unwrap() < 52.19
-            || stop.longitude.unwrap() < 12.46
-            || stop.longitude.unwrap() > 14.0
-        {
+/// Converts the result of a search into output stations. Stations that were not reached or
+/// that have no name or location are skipped.
+pub fn collect_reached_stations(
+    gtfs_rkyv: &gtfs_rkyv::ArchivedGtfsData,
+    all_connections_rkyv: &prepare_direct_connections_rkyv::ArchivedAllConnections,
+    station_states: &[StationState],
+) -> Vec<OutputStation> {
+    let mut stations = vec![];
+    for (station, station_state) in all_connections_rkyv.stations.iter().zip(station_states) {
+        let Some(earliest_arrival) = station_state.earliest_arrival else {
             continue;
-        }
+        };
+        let stop = &gtfs_rkyv.stops[station.main_stop_i.to_native() as usize];
+        let (Some(name), Some(latitude), Some(longitude)) = (
+            stop.name.as_ref(),
+            stop.latitude.as_ref(),
+            stop.longitude.as_ref(),
+        ) else {
+            continue;
+        };
+        stations.push(OutputStation {
+            id: stop.id.to_string(),
+            name: name.to_string(),
+            latitude: latitude.to_native(),
+            longitude: longitude.to_native(),
+            time: Some(earliest_arrival),
+        });
+    }
+    stations
+}
+
+/// Runs a search from the start stations and returns the resulting state of every station.
+pub fn find_earliest_arrivals(
+    stations: &[prepare_direct_connections_rkyv::ArchivedConnectionsFromStation],
+    start_station_indices: &[u32],
+    connection_filter: &ConnectionFilter,
+) -> Vec<StationState> {
+    let mut station_states = vec![
+        StationState {
+            earliest_arrival: None,
+        };
+        stations.len()
+    ];
+    let mut chunk_pool = ChunkedVectorPool::new();
+    find_optimal_paths_with_time_buckets(
+        stations,
+        start_station_indices,
+        &mut station_states,
+        &mut chunk_pool,
+        connection_filter,
+    );
+    station_states
+}
+
+/// Runs one search per origin, showing the progress, and passes the resulting state of every
+/// station to `handle_search`. The buffers are reused between the searches.
+pub fn search_from_each_origin(
+    stations: &[prepare_direct_connections_rkyv::ArchivedConnectionsFromStation],
+    origin_station_indices: &[u32],
+    connection_filter: &ConnectionFilter,
+    mut handle_search: impl FnMut(&[StationState]),
+) {
+    let style = indicatif::ProgressStyle::with_template(
+        "[{elapsed_precise}] {bar:40.cyan/blue} {human_pos:>7}/{human_len:7} {msg}",
+    )
+    .unwrap();
+
+    let mut station_states = vec![
+        StationState {
+            earliest_arrival: None,
+        };
+        stations.len()
+    ];
+    let mut chunk_pool = ChunkedVectorPool::new();
+    for origin_station_i in origin_station_indices
+        .iter()
+        .progress_with_style(style)
+        .with_message("Compute travel times.")
+        .with_finish(indicatif::ProgressFinish::AndLeave)
+    {
+        station_states.fill(StationState {
+            earliest_arrival: None,
+        });
+        find_optimal_paths_with_time_buckets(
+            stations,
+            &[*origin_station_i],
+            &mut station_states,
+            &mut chunk_pool,
+            connection_filter,
+        );
+        handle_search(&station_states);
+    }
+}
 
+/// Finds the indices of all stations whose main stop has one of the given names.
+pub fn find_station_indices_by_names(
+    gtfs_rkyv: &gtfs_rkyv::ArchivedGtfsData,
+    all_connections_rkyv: &prepare_direct_connections_rkyv::ArchivedAllConnections,
+    station_names: &[&str],
+) -> Vec<u32> {
+    let mut station_indices = vec![];
+    for (i, station) in all_connections_rkyv.stations.iter().enumerate() {
+        let stop = &gtfs_rkyv.stops[station.main_stop_i.to_native() as usize];
         if let Some(name) = stop.name.as_ref() {
-            if !name.contains("Hennigsdorf") {
-                continue;
+            if station_names.contains(&name.as_str()) {
+                station_indices.push(i as u32);
             }
         }
+    }
+    station_indices
+}
 
-        if let Some(earliest_arrival) = station_state.earliest_arrival {
-            result.stations.push(OutputStationWithTime {
-                name: stop.name.as_ref().unwrap().to_string(),
-                time: earliest_arrival,
-                latitude: stop.latitude.unwrap().to_native(),
-                longitude: stop.longitude.unwrap().to_native(),
-            });
+/// Numbers the arrival slots of all stations consecutively, so that a search can keep the
+/// earliest arrival of every slot in one vector. See `arrival_slots_num` for what a slot is.
+pub struct ArrivalSlots {
+    /// First slot of every station, followed by the total number of slots.
+    offsets: Vec<u32>,
+    /// Station of every slot.
+    station_indices: Vec<u32>,
+}
+
+impl ArrivalSlots {
+    pub fn new(
+        stations: &[prepare_direct_connections_rkyv::ArchivedConnectionsFromStation],
+    ) -> Self {
+        let mut offsets = Vec::with_capacity(stations.len() + 1);
+        let mut station_indices = vec![];
+        for (station_i, station) in stations.iter().enumerate() {
+            offsets.push(station_indices.len() as u32);
+            station_indices.extend(std::iter::repeat_n(
+                station_i as u32,
+                station.arrival_slots_num(),
+            ));
+        }
+        offsets.push(station_indices.len() as u32);
+        ArrivalSlots {
+            offsets,
+            station_indices,
         }
     }
 
-    let mut file = std::fs::File::create(
-        "/home/jacques/Documents/trip-atlas/frontend/src/stations_test_data.json",
-    )?;
-    file.write_all(serde_json::to_string_pretty(&result)?.as_bytes())?;
+    pub fn slots_num(&self) -> usize {
+        self.station_indices.len()
+    }
 
-    // println!(
-    //     "Station states: {:#?}",
-    //     station_states.iter().take(10000).collect::<Vec<_>>()
-    // );
+    pub fn slot_i(&self, station_i: u32, slot: usize) -> usize {
+        self.offsets[station_i as usize] as usize + slot
+    }
 
-    Ok(())
+    /// Slot at which searches from the station start.
+    pub fn start_slot_i(&self, station_i: u32) -> usize {
+        self.offsets[station_i as usize + 1] as usize - 1
+    }
+
+    /// Station of a slot and the slot within the station.
+    pub fn station_slot(&self, slot_i: usize) -> (u32, usize) {
+        let station_i = self.station_indices[slot_i];
+        (
+            station_i,
+            slot_i - self.offsets[station_i as usize] as usize,
+        )
+    }
 }
 
 fn _find_optimal_paths_with_binary_heap(
-    all_connections_rkyv: &prepare_direct_connections_rkyv::ArchivedAllConnections,
+    stations: &[prepare_direct_connections_rkyv::ArchivedConnectionsFromStation],
     start_station_indices: &[u32],
     station_states: &mut [StationState],
+    connection_filter: &ConnectionFilter,
 ) {
     #[derive(Debug, Clone, Copy)]
-    struct TimeWithStation {
+    struct TimeWithSlot {
         time: u32,
-        station_i: u32,
+        slot_i: usize,
     }
 
-    impl PartialEq for TimeWithStation {
+    impl PartialEq for TimeWithSlot {
         fn eq(&self, other: &Self) -> bool {
             self.time == other.time
         }
     }
 
-    impl PartialOrd for TimeWithStation {
+    impl PartialOrd for TimeWithSlot {
         fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-            Some(self.time.cmp(&other.time))
+            Some(self.cmp(other))
         }
     }
 
-    impl Eq for TimeWithStation {}
+    impl Eq for TimeWithSlot {}
 
-    impl Ord for TimeWithStation {
+    impl Ord for TimeWithSlot {
         fn cmp(&self, other: &Self) -> std::cmp::Ordering {
             self.time.cmp(&other.time)
         }
     }
 
+    let arrival_slots = ArrivalSlots::new(stations);
+    let mut slot_arrivals: Vec<Option<u32>> = vec![None; arrival_slots.slots_num()];
     let mut queue = BinaryHeap::new();
 
     for start_station_i in start_station_indices {
-        queue.push(Reverse(TimeWithStation {
-            time: 0,
-            station_i: *start_station_i,
-        }));
+        let slot_i = arrival_slots.start_slot_i(*start_station_i);
+        slot_arrivals[slot_i] = Some(0);
+        queue.push(Reverse(TimeWithSlot { time: 0, slot_i }));
         station_states[*start_station_i as usize].earliest_arrival = Some(0);
     }
 
     while let Some(event) = queue.pop() {
-        let station_i = event.0.station_i;
-        let station = &all_connections_rkyv.stations[station_i as usize];
+        let (station_i, slot) = arrival_slots.station_slot(event.0.slot_i);
+        let station = &stations[station_i as usize];
+        let stop_i = station.arrival_slot_stop_i(slot);
         for connection in station.connections.iter() {
+            if !connection_filter.allows(connection) {
+                continue;
+            }
+            let Some(transfer_time) = station.transfer_time(
+                stop_i,
+                connection.from_stop_i.to_native(),
+                connection_filter.step_free,
+            ) else {
+                continue;
+            };
             let next_station_i = connection.to_station_i.to_native();
-            let next_station_time = event.0.time + connection.duration;
-            let next_station_state = &mut station_states[next_station_i as usize];
-            if let Some(next_station_earliest_arrival) = next_station_state.earliest_arrival {
-                if next_station_time >= next_station_earliest_arrival {
+            let next_slot_i = arrival_slots.slot_i(
+                next_station_i,
+                stations[next_station_i as usize].arrival_slot(connection.to_stop_i.to_native()),
+            );
+            let next_time = event.0.time + transfer_time + connection.duration;
+            if let Some(next_slot_earliest_arrival) = slot_arrivals[next_slot_i] {
+                if next_time >= next_slot_earliest_arrival {
                     // Connection arrives at a later point than already found.
                     continue;
                 }
             }
-            next_station_state.earliest_arrival = Some(next_station_time);
-            queue.push(Reverse(TimeWithStation {
-                time: next_station_time,
-                station_i: next_station_i,
+            slot_arrivals[next_slot_i] = Some(next_time);
+            let next_station_state = &mut station_states[next_station_i as usize];
+            if next_station_state
+                .earliest_arrival
+                .is_none_or(|earliest_arrival| next_time < earliest_arrival)
+            {
+                next_station_state.earliest_arrival = Some(next_time);
+            }
+            queue.push(Reverse(TimeWithSlot {
+                time: next_time,
+                slot_i: next_slot_i,
             }));
         }
     }
 }
 
-fn find_optimal_paths_with_time_buckets(
-    all_connections_rkyv: &prepare_direct_connections_rkyv::ArchivedAllConnections,
+/// Computes the earliest arrival at every station when starting at time zero at any of the
+/// start stations. The station states are expected to be reset before calling this.
+///
+/// Arrivals are kept per arrival slot, so that an arrival at a platform with a shorter
+/// transfer is not discarded because another platform of the station was reached earlier.
+pub fn find_optimal_paths_with_time_buckets(
+    stations: &[prepare_direct_connections_rkyv::ArchivedConnectionsFromStation],
     start_station_indices: &[u32],
     station_states: &mut [StationState],
-    mut chunk_pool: &mut ChunkedVectorPool<u32>,
+    chunk_pool: &mut ChunkedVectorPool<u32>,
+    connection_filter: &ConnectionFilter,
 ) {
     struct Bucket {
-        station_indices: ChunkedVector<u32>,
+        slot_indices: ChunkedVector<u32>,
     }
 
     let max_seconds = 3000 * 60;
@@ -205,15 +359,18 @@ fn find_optimal_paths_with_time_buckets(
     buckets.reserve_exact(buckets_num);
     for _ in 0..buckets_num {
         buckets.push(Bucket {
-            station_indices: ChunkedVector::new(),
+            slot_indices: ChunkedVector::new(),
         });
     }
 
+    let arrival_slots = ArrivalSlots::new(stations);
+    let mut slot_arrivals: Vec<Option<u32>> = vec![None; arrival_slots.slots_num()];
+
     let first_bucket = &mut buckets[0];
     for station_i in start_station_indices {
-        first_bucket
-            .station_indices
-            .push(*station_i, &mut chunk_pool);
+        let slot_i = arrival_slots.start_slot_i(*station_i);
+        slot_arrivals[slot_i] = Some(0);
+        first_bucket.slot_indices.push(slot_i as u32, chunk_pool);
     }
 
     for station_i in start_station_indices {
@@ -226,42 +383,64 @@ fn find_optimal_paths_with_time_buckets(
         let (before_buckets, after_buckets) = buckets.split_at_mut(bucket_i + 1);
 
         let bucket = &mut before_buckets[bucket_i];
-        while !bucket.station_indices.is_empty() {
-            let mut new_station_indices = ChunkedVector::new();
-            let mut chunk_opt = bucket.station_indices.first_chunk();
+        while !bucket.slot_indices.is_empty() {
+            let mut new_slot_indices = ChunkedVector::new();
+            let mut chunk_opt = bucket.slot_indices.first_chunk();
             while let Some(chunk) = chunk_opt {
-                for station_i in chunk.get_slice() {
-                    let station = &all_connections_rkyv.stations[*station_i as usize];
+                for slot_i in chunk.get_slice() {
+                    let (station_i, slot) = arrival_slots.station_slot(*slot_i as usize);
+                    let station = &stations[station_i as usize];
+                    let stop_i = station.arrival_slot_stop_i(slot);
                     for connection in station.connections.iter() {
-                        let connection_duration = connection.duration.to_native() as usize;
+                        if !connection_filter.allows(connection) {
+                            continue;
+                        }
+                        let Some(transfer_time) = station.transfer_time(
+                            stop_i,
+                            connection.from_stop_i.to_native(),
+                            connection_filter.step_free,
+                        ) else {
+                            continue;
+                        };
+                        let connection_duration =
+                            (transfer_time + connection.duration.to_native()) as usize;
                         let next_station_i = connection.to_station_i.to_native();
-                        let next_station_state = &mut station_states[next_station_i as usize];
-                        let next_station_time = current_time + connection_duration;
-                        if let Some(next_station_earliest_arrival) =
-                            next_station_state.earliest_arrival
-                        {
-                            if next_station_time >= next_station_earliest_arrival as usize {
+                        let next_slot_i = arrival_slots.slot_i(
+                            next_station_i,
+                            stations[next_station_i as usize]
+                                .arrival_slot(connection.to_stop_i.to_native()),
+                        );
+                        let next_time = current_time + connection_duration;
+                        if let Some(next_slot_earliest_arrival) = slot_arrivals[next_slot_i] {
+                            if next_time >= next_slot_earliest_arrival as usize {
                                 // Connection arrives at a later point than already found.
                                 continue;
                             }
                         }
-                        next_station_state.earliest_arrival = Some(next_station_time as u32);
-                        let next_bucket_i = next_station_time / seconds_per_bucket;
+                        slot_arrivals[next_slot_i] = Some(next_time as u32);
+                        let next_station_state = &mut station_states[next_station_i as usize];
+                        if next_station_state
+                            .earliest_arrival
+                            .is_none_or(|earliest_arrival| next_time < earliest_arrival as usize)
+                        {
+                            next_station_state.earliest_arrival = Some(next_time as u32);
+                        }
+                        let next_bucket_i = next_time / seconds_per_bucket;
                         if next_bucket_i == bucket_i {
-                            new_station_indices.push(next_station_i, &mut chunk_pool);
+                            new_slot_indices.push(next_slot_i as u32, chunk_pool);
                         } else {
                             let next_bucket = &mut after_buckets[next_bucket_i - bucket_i - 1];
                             next_bucket
-                                .station_indices
-                                .push(next_station_i, &mut chunk_pool);
+                                .slot_indices
+                                .push(next_slot_i as u32, chunk_pool);
                         }
                     }
                 }
                 chunk_opt = chunk.next_chunk();
             }
 
-            bucket.station_indices.clear(&mut chunk_pool);
-            bucket.station_indices = new_station_indices;
+            bucket.slot_indices.clear(chunk_pool);
+            bucket.slot_indices = new_slot_indices;
         }
     }
 }
@@ -0,0 +1,42 @@
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "type")]
+pub struct FeatureCollection<Properties: serde::Serialize> {
+    pub features: Vec<Feature<Properties>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "type")]
+pub struct Feature<Properties: serde::Serialize> {
+    pub geometry: Geometry,
+    pub properties: Properties,
+}
+
+/// Coordinates are given as `[longitude, latitude]`.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "type", content = "coordinates")]
+pub enum Geometry {
+    Point([f64; 2]),
+    LineString(Vec<[f64; 2]>),
+    MultiPolygon(Vec<Vec<Vec<[f64; 2]>>>),
+}
+
+impl From<&geo::MultiPolygon<f64>> for Geometry {
+    fn from(multi_polygon: &geo::MultiPolygon<f64>) -> Self {
+        let ring_to_coordinates = |ring: &geo::LineString<f64>| {
+            ring.coords()
+                .map(|coord| [coord.x, coord.y])
+                .collect::<Vec<_>>()
+        };
+        Geometry::MultiPolygon(
+            multi_polygon
+                .iter()
+                .map(|polygon| {
+                    std::iter::once(polygon.exterior())
+                        .chain(polygon.interiors())
+                        .map(ring_to_coordinates)
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+}
@@ -2,11 +2,14 @@
 #[rkyv(derive(Debug))]
 pub struct GtfsData {
     pub agencies: Vec<GtfsAgency>,
-    pub calendars: Vec<GtfsCalendar>,
-    pub calendar_dates: Vec<GtfsCalendarDate>,
+    pub services: Vec<GtfsService>,
+    pub pathways: Vec<GtfsPathway>,
+    pub levels: Vec<GtfsLevel>,
     pub routes: Vec<GtfsRoute>,
+    /// Sorted by id.
+    pub shapes: Vec<GtfsShape>,
     pub stops: Vec<GtfsStop>,
-    pub stop_times: Vec<GtfsStopTime>,
+    pub stop_times: GtfsStopTimes,
     pub trips: Vec<GtfsTrip>,
 }
 
@@ -19,16 +22,85 @@ pub struct GtfsStop {
     pub parent_station_id: Option<String>,
     pub latitude: Option<f64>,
     pub longitude: Option<f64>,
+    pub location_type: GtfsLocationType,
+    pub level_id: Option<String>,
+    pub wheelchair_boarding: GtfsAvailability,
+    /// IANA timezone name for showing local times at the stop, the stop times themselves are
+    /// always in the agency timezone.
+    pub timezone: Option<String>,
 }
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
 #[rkyv(derive(Debug))]
+pub enum GtfsLocationType {
+    /// A stop or, if it has a parent station, a platform.
+    StopPoint,
+    Station,
+    StationEntrance,
+    GenericNode,
+    BoardingArea,
+    Other(i16),
+}
+
+impl ArchivedGtfsLocationType {
+    /// Whether vehicles can be boarded at this location.
+    pub fn is_boarding_location(&self) -> bool {
+        matches!(
+            self,
+            ArchivedGtfsLocationType::StopPoint | ArchivedGtfsLocationType::BoardingArea
+        )
+    }
+
+    /// Whether this location can be the main stop of a station, if it has no parent.
+    pub fn can_be_station(&self) -> bool {
+        matches!(
+            self,
+            ArchivedGtfsLocationType::StopPoint | ArchivedGtfsLocationType::Station
+        )
+    }
+}
+
+/// Whether a stop or trip is wheelchair accessible.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
+#[rkyv(derive(Debug))]
+pub enum GtfsAvailability {
+    /// For platforms, this means that the accessibility of the parent station applies.
+    InformationNotAvailable,
+    Available,
+    NotAvailable,
+    Other(i16),
+}
+
+/// A row of `stop_times.txt` while preparing the data, see `GtfsStopTimes` for how stop times
+/// are archived.
+#[derive(Debug)]
 pub struct GtfsStopTime {
     pub arrival_time: Option<u32>,
     pub departure_time: Option<u32>,
-    pub stop_id: String,
+    /// Index into `GtfsData::stops`.
+    pub stop_i: u32,
     pub stop_sequence: u16,
-    pub trip_id: String,
+    /// Index into `GtfsData::trips`.
+    pub trip_i: u32,
+    pub shape_dist_traveled: Option<f32>,
+    /// Whether the times were interpolated because the stop is not a timepoint in the feed.
+    pub interpolated: bool,
+}
+
+/// Stop times stored as columns, sorted by trip and then by stop sequence. The stop times of
+/// trip `trip_i` are at `trip_offsets[trip_i]..trip_offsets[trip_i + 1]` in every column.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
+#[rkyv(derive(Debug))]
+pub struct GtfsStopTimes {
+    /// One offset per trip plus the total number of stop times.
+    pub trip_offsets: Vec<u32>,
+    pub arrival_times: Vec<Option<u32>>,
+    pub departure_times: Vec<Option<u32>>,
+    /// Indices into `GtfsData::stops`.
+    pub stop_indices: Vec<u32>,
+    pub shape_dist_traveled: Vec<Option<f32>>,
+    /// Whether the times were interpolated because the stop is not a timepoint in the feed.
+    pub interpolated: Vec<bool>,
 }
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
@@ -38,6 +110,8 @@ pub struct GtfsTrip {
     pub service_id: String,
     pub route_id: String,
     pub short_name: Option<String>,
+    pub wheelchair_accessible: GtfsAvailability,
+    pub shape_id: Option<String>,
 }
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
@@ -46,8 +120,20 @@ pub struct GtfsRoute {
     pub id: String,
     pub short_name: Option<String>,
     pub long_name: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
     pub route_type: GtfsRouteType,
+    /// `route_type` as given in `routes.txt`. Unlike `route_type`, this keeps the extended
+    /// route types, e.g. 106 for regional rail, which `route_type` merges into the basic ones.
+    pub route_type_code: i16,
     pub agency_id: Option<String>,
+    /// `0xRRGGBB`, `None` if the feed doesn't give one, so that users can choose a default
+    /// per mode.
+    pub color: Option<u32>,
+    /// `0xRRGGBB`, `None` if the feed doesn't give one.
+    pub text_color: Option<u32>,
+    /// Routes with a lower order should be presented first.
+    pub sort_order: Option<u32>,
 }
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug, PartialEq, Eq, Hash)]
@@ -72,34 +158,91 @@ pub enum GtfsRouteType {
 pub struct GtfsAgency {
     pub id: Option<String>,
     pub name: String,
+    /// IANA timezone name. All agencies of a feed share the timezone, and stop times are
+    /// given in it.
+    pub timezone: String,
 }
 
+/// Days on which the trips of a service run. Services from `calendar.txt` and services that are
+/// only defined by `calendar_dates.txt` are represented the same way, the latter just without
+/// any weekdays. Days are given as the number of days since the common era, see `services.rs`.
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
 #[rkyv(derive(Debug))]
-pub struct GtfsCalendar {
+pub struct GtfsService {
     pub id: String,
-    pub monday: bool,
-    pub tuesday: bool,
-    pub wednesday: bool,
-    pub thursday: bool,
-    pub friday: bool,
-    pub saturday: bool,
-    pub sunday: bool,
-    pub start_date: String,
-    pub end_date: String,
+    /// Bit `i` is set if the service regularly runs on the `i`-th weekday, starting with
+    /// Monday.
+    pub weekdays: u8,
+    /// First and last day on which the regular weekdays apply. For services that are only
+    /// defined by `calendar_dates.txt`, the range spans the added days instead.
+    pub start_day: i32,
+    pub end_day: i32,
+    /// Sorted days on which the service runs in addition to the regular weekdays.
+    pub added_days: Vec<i32>,
+    /// Sorted days on which the service does not run although it regularly would.
+    pub removed_days: Vec<i32>,
 }
 
+/// Path between two locations within a station, e.g. from an entrance to a platform.
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
 #[rkyv(derive(Debug))]
-pub struct GtfsCalendarDate {
-    pub service_id: String,
-    pub date: String,
-    pub exception_type: GtfsExceptionType,
+pub struct GtfsPathway {
+    pub from_stop_id: String,
+    pub to_stop_id: String,
+    pub mode: GtfsPathwayMode,
+    pub is_bidirectional: bool,
+    /// Horizontal length in meters.
+    pub length: Option<f32>,
+    /// Time in seconds to walk from one end to the other.
+    pub traversal_time: Option<u32>,
+    pub stair_count: Option<i32>,
+}
+
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
+#[rkyv(derive(Debug))]
+pub enum GtfsPathwayMode {
+    Walkway,
+    Stairs,
+    MovingSidewalk,
+    Escalator,
+    Elevator,
+    FareGate,
+    ExitGate,
+}
+
+impl ArchivedGtfsPathwayMode {
+    /// Whether the pathway can be used without climbing stairs.
+    pub fn is_step_free(&self) -> bool {
+        !matches!(
+            self,
+            ArchivedGtfsPathwayMode::Stairs | ArchivedGtfsPathwayMode::Escalator
+        )
+    }
+}
+
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
+#[rkyv(derive(Debug))]
+pub struct GtfsLevel {
+    pub id: String,
+    /// Position of the level relative to the ground level, which has index 0.
+    pub index: f64,
+    pub name: Option<String>,
+}
+
+/// Path that the vehicles of a trip travel along.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
+#[rkyv(derive(Debug))]
+pub struct GtfsShape {
+    pub id: String,
+    /// Ordered by `shape_pt_sequence`.
+    pub points: Vec<GtfsShapePoint>,
 }
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
 #[rkyv(derive(Debug))]
-pub enum GtfsExceptionType {
-    Added,
-    Deleted,
+pub struct GtfsShapePoint {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// In the same unit as `GtfsStopTimes.shape_dist_traveled`.
+    pub dist_traveled: Option<f32>,
 }
@@ -0,0 +1,317 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+};
+
+use crate::{
+    connection_scan, gtfs_rkyv::ArchivedGtfsRouteType, prepare_direct_connections_rkyv,
+    prepare_gtfs_as_rkyv, services,
+};
+
+#[derive(Debug, Clone, Default)]
+struct RouteTypeStatistics {
+    name: String,
+    routes_num: usize,
+    trips_num: usize,
+    stops: HashSet<usize>,
+    stations_num: usize,
+}
+
+/// Prints an overview of the feed and of the connection graph that was derived from it.
+/// If a date is given, the services and trips running on that day are counted as well.
+pub async fn inspect_feed(gtfs_folder_path: &Path, date: Option<NaiveDate>) -> Result<()> {
+    let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
+    let all_connections_rkyv =
+        prepare_direct_connections_rkyv::load_direct_connections_rkyv(gtfs_folder_path).await?;
+
+    println!("Agencies:       {:>10}", gtfs_rkyv.agencies.len());
+    match gtfs_rkyv.feed_timezone() {
+        Ok(timezone) => println!("Timezone:       {:>10}", timezone.name()),
+        Err(error) => println!("Timezone:       {}", error),
+    }
+    println!("Routes:         {:>10}", gtfs_rkyv.routes.len());
+    println!("Trips:          {:>10}", gtfs_rkyv.trips.len());
+    println!("Stops:          {:>10}", gtfs_rkyv.stops.len());
+    println!(
+        "Stop times:     {:>10}",
+        gtfs_rkyv.stop_times.stop_indices.len()
+    );
+    println!(
+        "  interpolated: {:>10}",
+        gtfs_rkyv
+            .stop_times
+            .interpolated
+            .iter()
+            .filter(|interpolated| **interpolated)
+            .count()
+    );
+    println!(
+        "Stations:       {:>10}",
+        all_connections_rkyv.stations.len()
+    );
+
+    let exception_only_services_num = gtfs_rkyv
+        .services
+        .iter()
+        .filter(|service| service.weekdays == 0)
+        .count();
+    println!(
+        "Services:       {:>10} ({} only defined by calendar_dates.txt)",
+        gtfs_rkyv.services.len(),
+        exception_only_services_num
+    );
+    let first_day = gtfs_rkyv
+        .services
+        .iter()
+        .flat_map(|service| service.added_days.iter().chain([&service.start_day]))
+        .map(|day| day.to_native())
+        .min();
+    let last_day = gtfs_rkyv
+        .services
+        .iter()
+        .flat_map(|service| service.added_days.iter().chain([&service.end_day]))
+        .map(|day| day.to_native())
+        .max();
+    match (
+        first_day.and_then(services::date_from_day_number),
+        last_day.and_then(services::date_from_day_number),
+    ) {
+        (Some(first_date), Some(last_date)) => {
+            println!("Date range:     {} - {}", first_date, last_date)
+        }
+        _ => println!("Date range:     <no service dates>"),
+    }
+    if let Some(date) = date {
+        let running_service_ids: HashSet<&str> = gtfs_rkyv
+            .services
+            .iter()
+            .filter(|service| service.runs_on(date))
+            .map(|service| service.id.as_str())
+            .collect();
+        let running_trips_num = gtfs_rkyv
+            .trips
+            .iter()
+            .filter(|trip| running_service_ids.contains(trip.service_id.as_str()))
+            .count();
+        println!(
+            "On {}:  {} services, {} trips",
+            date,
+            running_service_ids.len(),
+            running_trips_num
+        );
+    }
+    println!();
+
+    let stop_index_by_id: HashMap<&str, usize> = gtfs_rkyv
+        .stops
+        .iter()
+        .enumerate()
+        .map(|(stop_i, stop)| (stop.id.as_str(), stop_i))
+        .collect();
+    let route_type_by_id: HashMap<&str, &ArchivedGtfsRouteType> = gtfs_rkyv
+        .routes
+        .iter()
+        .map(|route| (route.id.as_str(), &route.route_type))
+        .collect();
+    let trip_route_types: Vec<Option<&ArchivedGtfsRouteType>> = gtfs_rkyv
+        .trips
+        .iter()
+        .map(|trip| route_type_by_id.get(trip.route_id.as_str()).copied())
+        .collect();
+
+    let mut statistics: BTreeMap<i16, RouteTypeStatistics> = BTreeMap::new();
+    for route in gtfs_rkyv.routes.iter() {
+        route_type_statistics(&mut statistics, &route.route_type).routes_num += 1;
+    }
+    for route_type in trip_route_types.iter().flatten() {
+        route_type_statistics(&mut statistics, route_type).trips_num += 1;
+    }
+
+    let mut used_stops = vec![false; gtfs_rkyv.stops.len()];
+    let mut trips_without_stop_times_num = 0;
+    for (trip_i, route_type) in trip_route_types.iter().enumerate() {
+        let stop_time_range = gtfs_rkyv.stop_times.trip_range(trip_i);
+        if stop_time_range.is_empty() {
+            trips_without_stop_times_num += 1;
+        }
+        for stop_time_i in stop_time_range {
+            let stop_i = gtfs_rkyv.stop_times.stop_i(stop_time_i);
+            used_stops[stop_i] = true;
+            if let Some(route_type) = route_type {
+                route_type_statistics(&mut statistics, route_type)
+                    .stops
+                    .insert(stop_i);
+            }
+        }
+    }
+
+    let station_index_by_stop_i = connection_scan::station_index_by_stop_i(
+        &gtfs_rkyv,
+        &stop_index_by_id,
+        &all_connections_rkyv.stations,
+    );
+    for route_type_statistics in statistics.values_mut() {
+        route_type_statistics.stations_num = route_type_statistics
+            .stops
+            .iter()
+            .filter_map(|stop_i| station_index_by_stop_i[*stop_i])
+            .collect::<HashSet<u32>>()
+            .len();
+    }
+
+    println!(
+        "{:<12} {:>8} {:>10} {:>10} {:>10}",
+        "Route type", "Routes", "Trips", "Stops", "Stations"
+    );
+    for route_type_statistics in statistics.values() {
+        println!(
+            "{:<12} {:>8} {:>10} {:>10} {:>10}",
+            route_type_statistics.name,
+            route_type_statistics.routes_num,
+            route_type_statistics.trips_num,
+            route_type_statistics.stops.len(),
+            route_type_statistics.stations_num
+        );
+    }
+    println!();
+
+    // Stops are orphans if no trip stops there and they are not the parent of another stop.
+    let mut parent_stops = vec![false; gtfs_rkyv.stops.len()];
+    for stop in gtfs_rkyv.stops.iter() {
+        if let Some(parent_stop_i) = stop
+            .parent_station_id
+            .as_ref()
+            .and_then(|parent_station_id| stop_index_by_id.get(parent_station_id.as_str()))
+        {
+            parent_stops[*parent_stop_i] = true;
+        }
+    }
+    let orphan_stops_num = used_stops
+        .iter()
+        .zip(&parent_stops)
+        .filter(|(used, parent)| !**used && !**parent)
+        .count();
+    let stops_without_coordinates_num = gtfs_rkyv
+        .stops
+        .iter()
+        .filter(|stop| stop.latitude.is_none() || stop.longitude.is_none())
+        .count();
+    println!("Orphan stops:                {:>10}", orphan_stops_num);
+    println!(
+        "Stops without coordinates:   {:>10}",
+        stops_without_coordinates_num
+    );
+    println!(
+        "Trips without stop times:    {:>10}",
+        trips_without_stop_times_num
+    );
+    println!();
+
+    print_degree_distribution(&all_connections_rkyv);
+    Ok(())
+}
+
+/// Statistics are kept per route type code, so that every unknown route type gets its own
+/// entry.
+fn route_type_statistics<'a>(
+    statistics: &'a mut BTreeMap<i16, RouteTypeStatistics>,
+    route_type: &ArchivedGtfsRouteType,
+) -> &'a mut RouteTypeStatistics {
+    let code = match route_type {
+        ArchivedGtfsRouteType::Tramway => 0,
+        ArchivedGtfsRouteType::Subway => 1,
+        ArchivedGtfsRouteType::Rail => 2,
+        ArchivedGtfsRouteType::Bus => 3,
+        ArchivedGtfsRouteType::Ferry => 4,
+        ArchivedGtfsRouteType::CableCar => 5,
+        ArchivedGtfsRouteType::Gondola => 6,
+        ArchivedGtfsRouteType::Funicular => 7,
+        ArchivedGtfsRouteType::Coach => 200,
+        ArchivedGtfsRouteType::Air => 1100,
+        ArchivedGtfsRouteType::Taxi => 1500,
+        ArchivedGtfsRouteType::Other(code) => code.to_native(),
+    };
+    statistics
+        .entry(code)
+        .or_insert_with(|| RouteTypeStatistics {
+            name: match route_type {
+                ArchivedGtfsRouteType::Other(_) => format!("Other {}", code),
+                route_type => format!("{:?}", route_type),
+            },
+            ..Default::default()
+        })
+}
+
+/// Prints a histogram of the number of outgoing and incoming connections per station, using
+/// buckets that double in size.
+fn print_degree_distribution(
+    all_connections_rkyv: &prepare_direct_connections_rkyv::ArchivedAllConnections,
+) {
+    let bucket_index = |degree: usize| match degree {
+        0 => 0,
+        degree => degree.ilog2() as usize + 1,
+    };
+    let mut out_degree_counts: Vec<usize> = vec![];
+    let mut in_degree_counts: Vec<usize> = vec![];
+    let mut max_degrees = (0, 0);
+    let mut degree_sums = (0, 0);
+    for (station, reversed_station) in all_connections_rkyv
+        .stations
+        .iter()
+        .zip(all_connections_rkyv.reversed_stations.iter())
+    {
+        for (degree, counts, max_degree, degree_sum) in [
+            (
+                station.connections.len(),
+                &mut out_degree_counts,
+                &mut max_degrees.0,
+                &mut degree_sums.0,
+            ),
+            (
+                reversed_station.connections.len(),
+                &mut in_degree_counts,
+                &mut max_degrees.1,
+                &mut degree_sums.1,
+            ),
+        ] {
+            let bucket_i = bucket_index(degree);
+            if counts.len() <= bucket_i {
+                counts.resize(bucket_i + 1, 0);
+            }
+            counts[bucket_i] += 1;
+            *max_degree = (*max_degree).max(degree);
+            *degree_sum += degree;
+        }
+    }
+
+    let stations_num = all_connections_rkyv.stations.len().max(1) as f64;
+    println!(
+        "Outgoing connections per station: mean {:.2}, max {}",
+        degree_sums.0 as f64 / stations_num,
+        max_degrees.0
+    );
+    println!(
+        "Incoming connections per station: mean {:.2}, max {}",
+        degree_sums.1 as f64 / stations_num,
+        max_degrees.1
+    );
+    println!(
+        "{:<14} {:>10} {:>10}",
+        "Connections", "Outgoing", "Incoming"
+    );
+    for bucket_i in 0..out_degree_counts.len().max(in_degree_counts.len()) {
+        let label = match bucket_i {
+            0 => "0".to_string(),
+            1 => "1".to_string(),
+            bucket_i => format!("{}-{}", 1 << (bucket_i - 1), (1 << bucket_i) - 1),
+        };
+        println!(
+            "{:<14} {:>10} {:>10}",
+            label,
+            out_degree_counts.get(bucket_i).copied().unwrap_or(0),
+            in_degree_counts.get(bucket_i).copied().unwrap_or(0)
+        );
+    }
+}
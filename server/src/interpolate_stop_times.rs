@@ -0,0 +1,328 @@
+use geo::Distance;
+use std::collections::HashMap;
+
+use crate::gtfs_rkyv::{GtfsStop, GtfsStopTime};
+
+/// Fills in the times of stops that are not timepoints. The time of an untimed stop is
+/// interpolated between the surrounding timed stops of the trip, proportionally to the distance
+/// travelled. The distance is taken from `shape_dist_traveled` if every stop time of the trip
+/// has it, otherwise from the straight-line distances between the stops. Untimed stops before
+/// the first or after the last timed stop of a trip are left as they are.
+///
+/// Stop times with only an arrival or only a departure time get the missing one set to the
+/// given one, so that they have both an incoming and an outgoing connection.
+///
+/// Returns the number of stop times whose times were interpolated.
+pub fn interpolate_stop_times(stop_times: &mut [GtfsStopTime], stops: &[GtfsStop]) -> usize {
+    let mut interpolated_num = 0;
+    for stop_time in stop_times.iter_mut() {
+        match (stop_time.arrival_time, stop_time.departure_time) {
+            (Some(time), None) | (None, Some(time)) => {
+                stop_time.arrival_time = Some(time);
+                stop_time.departure_time = Some(time);
+                stop_time.interpolated = true;
+                interpolated_num += 1;
+            }
+            _ => {}
+        }
+    }
+
+    let stop_by_id: HashMap<&str, &GtfsStop> =
+        stops.iter().map(|stop| (stop.id.as_str(), stop)).collect();
+    let stop_location = |stop_i: u32| {
+        let stop = &stops[stop_i as usize];
+        // Platforms often only inherit their location from the station.
+        let location_stop = match (stop.latitude, stop.longitude, &stop.parent_station_id) {
+            (Some(_), Some(_), _) => stop,
+            (_, _, Some(parent_station_id)) => stop_by_id.get(parent_station_id.as_str())?,
+            _ => return None,
+        };
+        Some(geo::Point::new(
+            location_stop.longitude?,
+            location_stop.latitude?,
+        ))
+    };
+
+    let mut order: Vec<usize> = (0..stop_times.len()).collect();
+    order.sort_by(|a, b| {
+        let (a, b) = (&stop_times[*a], &stop_times[*b]);
+        a.trip_i
+            .cmp(&b.trip_i)
+            .then(a.stop_sequence.cmp(&b.stop_sequence))
+    });
+
+    let trip_lengths: Vec<usize> = order
+        .chunk_by(|a, b| stop_times[*a].trip_i == stop_times[*b].trip_i)
+        .map(|trip_order| trip_order.len())
+        .collect();
+
+    let mut trip_start = 0;
+    for trip_length in trip_lengths {
+        let trip_order = &order[trip_start..trip_start + trip_length];
+        trip_start += trip_length;
+        let is_untimed = |stop_time: &GtfsStopTime| {
+            stop_time.arrival_time.is_none() && stop_time.departure_time.is_none()
+        };
+        if !trip_order.iter().any(|i| is_untimed(&stop_times[*i])) {
+            continue;
+        }
+
+        let distances = trip_distances(stop_times, trip_order, &stop_location);
+        let mut previous_timed: Option<(usize, u32)> = None;
+        for (position, stop_time_i) in trip_order.iter().enumerate() {
+            let stop_time = &stop_times[*stop_time_i];
+            if is_untimed(stop_time) {
+                continue;
+            }
+            let arrival_time = stop_time.arrival_time.or(stop_time.departure_time).unwrap();
+            let departure_time = stop_time.departure_time.or(stop_time.arrival_time).unwrap();
+            if let Some((previous_position, previous_departure_time)) = previous_timed {
+                for untimed_position in previous_position + 1..position {
+                    let fraction = interpolation_fraction(
+                        &distances,
+                        previous_position,
+                        untimed_position,
+                        position,
+                    );
+                    let duration = arrival_time.saturating_sub(previous_departure_time);
+                    let time =
+                        previous_departure_time + (duration as f64 * fraction).round() as u32;
+                    let untimed_stop_time = &mut stop_times[trip_order[untimed_position]];
+                    untimed_stop_time.arrival_time = Some(time);
+                    untimed_stop_time.departure_time = Some(time);
+                    untimed_stop_time.interpolated = true;
+                    interpolated_num += 1;
+                }
+            }
+            previous_timed = Some((position, departure_time));
+        }
+    }
+    interpolated_num
+}
+
+/// Distance travelled up to every stop time of a trip, or `None` if it cannot be determined
+/// for all of them.
+fn trip_distances(
+    stop_times: &[GtfsStopTime],
+    trip_order: &[usize],
+    stop_location: &impl Fn(u32) -> Option<geo::Point<f64>>,
+) -> Option<Vec<f64>> {
+    let shape_distances: Option<Vec<f64>> = trip_order
+        .iter()
+        .map(|i| {
+            stop_times[*i]
+                .shape_dist_traveled
+                .map(|distance| distance as f64)
+        })
+        .collect();
+    if shape_distances.is_some() {
+        return shape_distances;
+    }
+
+    let locations: Option<Vec<geo::Point<f64>>> = trip_order
+        .iter()
+        .map(|i| stop_location(stop_times[*i].stop_i))
+        .collect();
+    let locations = locations?;
+    let mut distance = 0.0;
+    let mut distances = Vec::with_capacity(locations.len());
+    distances.push(distance);
+    for pair in locations.windows(2) {
+        distance += geo::Haversine.distance(pair[0], pair[1]);
+        distances.push(distance);
+    }
+    Some(distances)
+}
+
+/// Fraction of the way from the previous to the next timed stop. Falls back to counting stops
+/// if the distances are unknown or do not increase.
+fn interpolation_fraction(
+    distances: &Option<Vec<f64>>,
+    previous_position: usize,
+    position: usize,
+    next_position: usize,
+) -> f64 {
+    if let Some(distances) = distances {
+        let total_distance = distances[next_position] - distances[previous_position];
+        let distance = distances[position] - distances[previous_position];
+        if total_distance > 0.0 && distance >= 0.0 {
+            return (distance / total_distance).min(1.0);
+        }
+    }
+    (position - previous_position) as f64 / (next_position - previous_position) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs_rkyv::{GtfsAvailability, GtfsLocationType};
+
+    fn stop(id: &str, longitude: Option<f64>, parent_station_id: Option<&str>) -> GtfsStop {
+        GtfsStop {
+            id: id.to_string(),
+            code: None,
+            name: None,
+            parent_station_id: parent_station_id.map(|id| id.to_string()),
+            latitude: longitude.map(|_| 0.0),
+            longitude,
+            location_type: GtfsLocationType::StopPoint,
+            level_id: None,
+            wheelchair_boarding: GtfsAvailability::InformationNotAvailable,
+            timezone: None,
+        }
+    }
+
+    fn stop_time(
+        trip_i: u32,
+        stop_sequence: u16,
+        times: (Option<u32>, Option<u32>),
+        shape_dist_traveled: Option<f32>,
+    ) -> GtfsStopTime {
+        GtfsStopTime {
+            arrival_time: times.0,
+            departure_time: times.1,
+            stop_i: stop_sequence as u32,
+            stop_sequence,
+            trip_i,
+            shape_dist_traveled,
+            interpolated: false,
+        }
+    }
+
+    fn timed(time: u32) -> (Option<u32>, Option<u32>) {
+        (Some(time), Some(time))
+    }
+
+    const UNTIMED: (Option<u32>, Option<u32>) = (None, None);
+
+    /// Stops on the equator, one hundredth of a degree apart.
+    fn stops_on_equator(num: usize) -> Vec<GtfsStop> {
+        (0..num)
+            .map(|i| stop(&format!("S{}", i), Some(i as f64 * 0.01), None))
+            .collect()
+    }
+
+    fn times(stop_times: &[GtfsStopTime]) -> Vec<Option<u32>> {
+        stop_times
+            .iter()
+            .map(|stop_time| stop_time.arrival_time)
+            .collect()
+    }
+
+    #[test]
+    fn interpolates_runs_by_shape_distance() {
+        let mut stop_times = vec![
+            stop_time(0, 0, (Some(0), Some(60)), Some(0.0)),
+            stop_time(0, 1, UNTIMED, Some(100.0)),
+            stop_time(0, 2, UNTIMED, Some(400.0)),
+            stop_time(0, 3, timed(1060), Some(1000.0)),
+        ];
+        assert_eq!(
+            interpolate_stop_times(&mut stop_times, &stops_on_equator(4)),
+            2
+        );
+        // Measured from the departure at the previous timed stop.
+        assert_eq!(
+            times(&stop_times),
+            vec![Some(0), Some(160), Some(460), Some(1060)]
+        );
+        assert_eq!(stop_times[1].departure_time, Some(160));
+        let interpolated: Vec<bool> = stop_times
+            .iter()
+            .map(|stop_time| stop_time.interpolated)
+            .collect();
+        assert_eq!(interpolated, vec![false, true, true, false]);
+    }
+
+    #[test]
+    fn falls_back_to_straight_line_distances() {
+        // The shape distance is missing at one stop, so the locations are used. The second
+        // stop is a platform that takes the location of its station.
+        let stops = vec![
+            stop("S0", Some(0.0), None),
+            stop("S1", None, Some("P")),
+            stop("S2", Some(0.03), None),
+            stop("P", Some(0.01), None),
+        ];
+        let mut stop_times = vec![
+            stop_time(0, 0, timed(0), Some(0.0)),
+            stop_time(0, 1, UNTIMED, None),
+            stop_time(0, 2, timed(300), Some(10.0)),
+        ];
+        assert_eq!(interpolate_stop_times(&mut stop_times, &stops), 1);
+        assert_eq!(times(&stop_times), vec![Some(0), Some(100), Some(300)]);
+    }
+
+    #[test]
+    fn leaves_untimed_stops_at_trip_ends() {
+        let mut stop_times = vec![
+            stop_time(0, 0, UNTIMED, None),
+            stop_time(0, 1, timed(100), None),
+            stop_time(0, 2, UNTIMED, None),
+            stop_time(0, 3, timed(200), None),
+            stop_time(0, 4, UNTIMED, None),
+        ];
+        assert_eq!(
+            interpolate_stop_times(&mut stop_times, &stops_on_equator(5)),
+            1
+        );
+        assert_eq!(
+            times(&stop_times),
+            vec![None, Some(100), Some(150), Some(200), None]
+        );
+        assert!(!stop_times[0].interpolated && !stop_times[4].interpolated);
+    }
+
+    #[test]
+    fn counts_stops_without_distance() {
+        let mut stop_times = vec![
+            stop_time(0, 0, timed(0), Some(5.0)),
+            stop_time(0, 1, UNTIMED, Some(5.0)),
+            stop_time(0, 2, UNTIMED, Some(5.0)),
+            stop_time(0, 3, timed(300), Some(5.0)),
+        ];
+        assert_eq!(
+            interpolate_stop_times(&mut stop_times, &stops_on_equator(4)),
+            2
+        );
+        assert_eq!(
+            times(&stop_times),
+            vec![Some(0), Some(100), Some(200), Some(300)]
+        );
+    }
+
+    #[test]
+    fn fills_missing_arrival_or_departure() {
+        let mut stop_times = vec![
+            stop_time(0, 0, (None, Some(0)), None),
+            stop_time(0, 1, UNTIMED, None),
+            stop_time(0, 2, (Some(200), None), None),
+        ];
+        assert_eq!(
+            interpolate_stop_times(&mut stop_times, &stops_on_equator(3)),
+            3
+        );
+        assert_eq!(stop_times[0].arrival_time, Some(0));
+        assert_eq!(stop_times[1].arrival_time, Some(100));
+        assert_eq!(stop_times[2].departure_time, Some(200));
+        assert!(stop_times.iter().all(|stop_time| stop_time.interpolated));
+    }
+
+    #[test]
+    fn keeps_trips_apart_in_unsorted_input() {
+        let mut stop_times = vec![
+            stop_time(1, 2, timed(1000), None),
+            stop_time(0, 1, UNTIMED, None),
+            stop_time(1, 0, timed(0), None),
+            stop_time(0, 2, timed(200), None),
+            stop_time(1, 1, UNTIMED, None),
+            stop_time(0, 0, timed(0), None),
+        ];
+        assert_eq!(
+            interpolate_stop_times(&mut stop_times, &stops_on_equator(3)),
+            2
+        );
+        assert_eq!(stop_times[1].arrival_time, Some(100));
+        assert_eq!(stop_times[4].arrival_time, Some(500));
+    }
+}
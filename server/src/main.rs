@@ -1,18 +1,48 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
+use connection_filter::ConnectionFilterArgs;
+use station_output::StationOutputArgs;
 use std::path::Path;
+use walking::WalkingArgs;
 
+mod agency_statistics;
+mod compression;
+mod connection_filter;
+mod connection_scan;
+mod export_heatmap;
+mod export_isochrones;
+mod export_mvt_tiles;
+mod export_route_lines;
 mod export_station_locations;
+mod export_station_tiles;
+mod export_trip_geometries;
+mod find_latest_departures;
 mod find_optimal_paths;
+mod geojson;
 mod gtfs_rkyv;
+mod inspect_feed;
+mod interpolate_stop_times;
 mod memory_mapped_rkyv;
+mod mvt;
 mod pooled_chunked_vector;
 mod prepare_direct_connections_rkyv;
 mod prepare_gtfs_as_rkyv;
+mod services;
+mod shapes;
+mod station_output;
+mod station_transfers;
+mod stop_times;
+mod timezones;
+mod travel_time_matrix;
+mod travel_time_store;
+mod validate_gtfs;
+mod varint;
+mod walking;
+mod web_mercator;
 
 #[derive(Parser, Debug)]
 #[command(name = "trip-atlas")]
-struct CLI {
+struct Cli {
     #[command(subcommand)]
     command: CLICommand,
 }
@@ -26,12 +56,185 @@ enum CLICommand {
     ExportStationLocations {
         #[arg(long)]
         gtfs_path: String,
+        #[command(flatten)]
+        output: StationOutputArgs,
+    },
+    FindOptimalPaths {
+        #[arg(long)]
+        gtfs_path: String,
+        #[command(flatten)]
+        output: StationOutputArgs,
+        #[command(flatten)]
+        connection_filter: ConnectionFilterArgs,
+    },
+    FindLatestDepartures {
+        #[arg(long)]
+        gtfs_path: String,
+        /// Name of a station that should be reached, can be passed multiple times.
+        #[arg(long = "station", required = true)]
+        station_names: Vec<String>,
+        /// Latest arrival time at the target stations, e.g. `09:00`.
+        #[arg(long)]
+        arrival_time: String,
+        #[command(flatten)]
+        output: StationOutputArgs,
+        #[command(flatten)]
+        connection_filter: ConnectionFilterArgs,
+    },
+    FindTimetableArrivals {
+        #[arg(long)]
+        gtfs_path: String,
+        /// Name of a start station, can be passed multiple times.
+        #[arg(long = "station", required = true)]
+        station_names: Vec<String>,
+        #[command(flatten)]
+        search: connection_scan::TimetableSearchArgs,
+        #[command(flatten)]
+        output: StationOutputArgs,
+        #[command(flatten)]
+        connection_filter: ConnectionFilterArgs,
+    },
+    ExportHeatmap {
+        #[arg(long)]
+        gtfs_path: String,
+        /// Name of a start station, can be passed multiple times.
+        #[arg(long = "station", required = true)]
+        station_names: Vec<String>,
+        #[command(flatten)]
+        heatmap: export_heatmap::HeatmapArgs,
+        #[command(flatten)]
+        walking: WalkingArgs,
+        #[command(flatten)]
+        connection_filter: ConnectionFilterArgs,
+    },
+    ExportIsochrones {
+        #[arg(long)]
+        gtfs_path: String,
+        /// Name of a start station, can be passed multiple times.
+        #[arg(long = "station", required = true)]
+        station_names: Vec<String>,
+        /// Upper bounds of the time bands in minutes.
+        #[arg(long = "band", default_values_t = [15, 30, 45, 60])]
+        band_minutes: Vec<u32>,
         #[arg(long)]
         output_path: String,
+        #[command(flatten)]
+        walking: WalkingArgs,
+        #[command(flatten)]
+        connection_filter: ConnectionFilterArgs,
     },
-    FindOptimalPaths {
+    ExportMvtTiles {
+        #[arg(long)]
+        gtfs_path: String,
+        /// Name of a start station, can be passed multiple times.
+        #[arg(long = "station", required = true)]
+        station_names: Vec<String>,
+        #[command(flatten)]
+        tiles: export_mvt_tiles::MvtTilesArgs,
+        #[command(flatten)]
+        walking: WalkingArgs,
+        #[command(flatten)]
+        connection_filter: ConnectionFilterArgs,
+    },
+    ExportRouteLines {
+        #[arg(long)]
+        gtfs_path: String,
+        #[command(flatten)]
+        route_lines: export_route_lines::RouteLinesArgs,
+    },
+    ExportStationTiles {
+        #[arg(long)]
+        gtfs_path: String,
+        /// Name of a start station to include travel times, can be passed multiple times.
+        #[arg(long = "station")]
+        station_names: Vec<String>,
+        #[command(flatten)]
+        tiles: export_station_tiles::StationTilesArgs,
+        #[command(flatten)]
+        connection_filter: ConnectionFilterArgs,
+    },
+    ExportTripGeometries {
+        #[arg(long)]
+        gtfs_path: String,
+        /// Id of a trip to export, can be passed multiple times.
+        #[arg(long = "trip", required = true)]
+        trip_ids: Vec<String>,
+        /// Service day in the format `YYYY-MM-DD`, to also write the times as timestamps.
+        #[arg(long)]
+        date: Option<chrono::NaiveDate>,
+        #[arg(long)]
+        output_path: String,
+    },
+    TravelTimeMatrix {
+        #[arg(long)]
+        gtfs_path: String,
+        /// Name of a station used as origin and destination, can be passed multiple times.
+        #[arg(long = "station", required = true)]
+        station_names: Vec<String>,
+        #[arg(long)]
+        output_path: String,
+        #[arg(long, value_enum, default_value_t = travel_time_matrix::TravelTimeMatrixFormat::Binary)]
+        format: travel_time_matrix::TravelTimeMatrixFormat,
+        #[command(flatten)]
+        connection_filter: ConnectionFilterArgs,
+    },
+    BuildTravelTimeStore {
+        #[arg(long)]
+        gtfs_path: String,
+        /// Name of an origin station, can be passed multiple times. Without any, all stations
+        /// are used as origins.
+        #[arg(long = "station")]
+        station_names: Vec<String>,
+        #[arg(long)]
+        output_path: String,
+        /// Resolution of the stored travel times.
+        #[arg(long, default_value_t = 60)]
+        seconds_per_unit: u32,
+        #[command(flatten)]
+        connection_filter: ConnectionFilterArgs,
+    },
+    QueryTravelTimeStore {
         #[arg(long)]
         gtfs_path: String,
+        #[arg(long)]
+        store_path: String,
+        #[arg(long = "station")]
+        station_name: String,
+        #[command(flatten)]
+        output: StationOutputArgs,
+        /// Has to match the options the store was built with.
+        #[command(flatten)]
+        connection_filter: ConnectionFilterArgs,
+    },
+    Validate {
+        #[arg(long)]
+        gtfs_path: String,
+        /// Where the full report is written to. Without it only a summary is printed.
+        #[arg(long)]
+        output_path: Option<String>,
+        #[arg(long, value_enum, default_value_t = validate_gtfs::ValidationReportFormat::Json)]
+        format: validate_gtfs::ValidationReportFormat,
+    },
+    Inspect {
+        #[arg(long)]
+        gtfs_path: String,
+        /// Day in the format `YYYY-MM-DD` for which the running trips are counted.
+        #[arg(long)]
+        date: Option<chrono::NaiveDate>,
+    },
+    AgencyStatistics {
+        #[arg(long)]
+        gtfs_path: String,
+    },
+    ConvertTravelTimeMatrix {
+        #[arg(long)]
+        gtfs_path: String,
+        #[arg(long)]
+        matrix_path: String,
+        #[arg(long)]
+        output_path: String,
+        #[arg(long, value_enum)]
+        format: travel_time_matrix::TravelTimeMatrixFormat,
     },
 }
 
@@ -39,23 +242,222 @@ enum CLICommand {
 async fn main() -> Result<()> {
     simple_logger::SimpleLogger::new().init()?;
 
-    let cli = CLI::parse();
+    let cli = Cli::parse();
     match cli.command {
         CLICommand::PrepareGTFS { gtfs_path } => {
             prepare_gtfs_as_rkyv::ensure_gtfs_folder_rkyv(Path::new(&gtfs_path)).await?;
         }
-        CLICommand::ExportStationLocations {
+        CLICommand::ExportStationLocations { gtfs_path, output } => {
+            export_station_locations::export_station_locations(Path::new(&gtfs_path), &output)
+                .await?;
+        }
+        CLICommand::FindOptimalPaths {
+            gtfs_path,
+            output,
+            connection_filter,
+        } => {
+            find_optimal_paths::find_optimal_paths(
+                Path::new(&gtfs_path),
+                &output,
+                &connection_filter,
+            )
+            .await?;
+        }
+        CLICommand::FindLatestDepartures {
+            gtfs_path,
+            station_names,
+            arrival_time,
+            output,
+            connection_filter,
+        } => {
+            find_latest_departures::find_latest_departures(
+                Path::new(&gtfs_path),
+                &station_names,
+                &arrival_time,
+                &output,
+                &connection_filter,
+            )
+            .await?;
+        }
+        CLICommand::FindTimetableArrivals {
             gtfs_path,
+            station_names,
+            search,
+            output,
+            connection_filter,
+        } => {
+            connection_scan::find_timetable_arrivals(
+                Path::new(&gtfs_path),
+                &station_names,
+                &search,
+                &output,
+                &connection_filter,
+            )
+            .await?;
+        }
+        CLICommand::ExportHeatmap {
+            gtfs_path,
+            station_names,
+            heatmap,
+            walking,
+            connection_filter,
+        } => {
+            export_heatmap::export_heatmap(
+                Path::new(&gtfs_path),
+                &station_names,
+                &heatmap,
+                &walking,
+                &connection_filter,
+            )
+            .await?;
+        }
+        CLICommand::ExportIsochrones {
+            gtfs_path,
+            station_names,
+            band_minutes,
             output_path,
+            walking,
+            connection_filter,
         } => {
-            export_station_locations::export_station_locations(
+            export_isochrones::export_isochrones(
                 Path::new(&gtfs_path),
+                &station_names,
+                &band_minutes,
                 Path::new(&output_path),
+                &walking,
+                &connection_filter,
             )
             .await?;
         }
-        CLICommand::FindOptimalPaths { gtfs_path } => {
-            find_optimal_paths::find_optimal_paths(Path::new(&gtfs_path)).await?;
+        CLICommand::ExportMvtTiles {
+            gtfs_path,
+            station_names,
+            tiles,
+            walking,
+            connection_filter,
+        } => {
+            export_mvt_tiles::export_mvt_tiles(
+                Path::new(&gtfs_path),
+                &station_names,
+                &tiles,
+                &walking,
+                &connection_filter,
+            )
+            .await?;
+        }
+        CLICommand::ExportRouteLines {
+            gtfs_path,
+            route_lines,
+        } => {
+            export_route_lines::export_route_lines(Path::new(&gtfs_path), &route_lines).await?;
+        }
+        CLICommand::ExportStationTiles {
+            gtfs_path,
+            station_names,
+            tiles,
+            connection_filter,
+        } => {
+            export_station_tiles::export_station_tiles(
+                Path::new(&gtfs_path),
+                &station_names,
+                &tiles,
+                &connection_filter,
+            )
+            .await?;
+        }
+        CLICommand::ExportTripGeometries {
+            gtfs_path,
+            trip_ids,
+            date,
+            output_path,
+        } => {
+            export_trip_geometries::export_trip_geometries(
+                Path::new(&gtfs_path),
+                &trip_ids,
+                date,
+                Path::new(&output_path),
+            )
+            .await?;
+        }
+        CLICommand::TravelTimeMatrix {
+            gtfs_path,
+            station_names,
+            output_path,
+            format,
+            connection_filter,
+        } => {
+            travel_time_matrix::export_travel_time_matrix(
+                Path::new(&gtfs_path),
+                &station_names,
+                Path::new(&output_path),
+                format,
+                &connection_filter,
+            )
+            .await?;
+        }
+        CLICommand::BuildTravelTimeStore {
+            gtfs_path,
+            station_names,
+            output_path,
+            seconds_per_unit,
+            connection_filter,
+        } => {
+            travel_time_store::build_travel_time_store(
+                Path::new(&gtfs_path),
+                &station_names,
+                Path::new(&output_path),
+                seconds_per_unit,
+                &connection_filter,
+            )
+            .await?;
+        }
+        CLICommand::QueryTravelTimeStore {
+            gtfs_path,
+            store_path,
+            station_name,
+            output,
+            connection_filter,
+        } => {
+            travel_time_store::query_travel_time_store(
+                Path::new(&gtfs_path),
+                Path::new(&store_path),
+                &station_name,
+                &output,
+                &connection_filter,
+            )
+            .await?;
+        }
+        CLICommand::Validate {
+            gtfs_path,
+            output_path,
+            format,
+        } => {
+            validate_gtfs::validate_gtfs(
+                Path::new(&gtfs_path),
+                output_path.as_deref().map(Path::new),
+                format,
+            )
+            .await?;
+        }
+        CLICommand::Inspect { gtfs_path, date } => {
+            inspect_feed::inspect_feed(Path::new(&gtfs_path), date).await?;
+        }
+        CLICommand::AgencyStatistics { gtfs_path } => {
+            agency_statistics::print_agency_statistics(Path::new(&gtfs_path)).await?;
+        }
+        CLICommand::ConvertTravelTimeMatrix {
+            gtfs_path,
+            matrix_path,
+            output_path,
+            format,
+        } => {
+            travel_time_matrix::convert_travel_time_matrix(
+                Path::new(&gtfs_path),
+                Path::new(&matrix_path),
+                Path::new(&output_path),
+                format,
+            )
+            .await?;
         }
     }
     Ok(())
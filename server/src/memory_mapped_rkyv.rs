@@ -28,3 +28,26 @@ pub async unsafe fn load_memory_mapped_rkyv<'a, Archive: rkyv::Portable + 'a>(
         data: rkyv_data,
     })
 }
+
+/// Like `load_memory_mapped_rkyv`, but validates the archive first. Use this for files that
+/// are passed in by the user rather than prepared by this program.
+// Safety: This is safe for as long as the underlying file is not modified.
+pub async unsafe fn load_memory_mapped_rkyv_checked<'a, Archive>(
+    path: &Path,
+) -> Result<MemoryMappedRkyv<'a, Archive>>
+where
+    Archive: rkyv::Portable
+        + for<'b> rkyv::bytecheck::CheckBytes<rkyv::api::high::HighValidator<'b, rkyv::rancor::Error>>
+        + 'a,
+{
+    let file = std::fs::File::open(path)?;
+    // Safety: This is safe for as long as the underlying file is not modified.
+    let mmap = unsafe { memmap2::Mmap::map(&file)? };
+    let buffer: &[u8] = unsafe { std::slice::from_raw_parts(mmap.as_ptr(), mmap.len()) };
+    let rkyv_data = rkyv::access::<Archive, rkyv::rancor::Error>(buffer)
+        .map_err(|error| anyhow::anyhow!("Invalid file {:?}: {}", path, error))?;
+    Ok(MemoryMappedRkyv {
+        _mmap: mmap,
+        data: rkyv_data,
+    })
+}
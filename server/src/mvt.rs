@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+
+use crate::varint::write_varint;
+
+/// Default number of integer coordinate steps along each side of a tile.
+pub const DEFAULT_EXTENT: u32 = 4096;
+
+const GEOMETRY_TYPE_POINT: u64 = 1;
+const GEOMETRY_TYPE_POLYGON: u64 = 3;
+
+const COMMAND_MOVE_TO: u32 = 1;
+const COMMAND_LINE_TO: u32 = 2;
+const COMMAND_CLOSE_PATH: u32 = 7;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MvtValue {
+    String(String),
+    UInt(u64),
+}
+
+struct MvtFeature {
+    tags: Vec<u32>,
+    geometry_type: u64,
+    geometry: Vec<u32>,
+}
+
+/// One layer of a Mapbox Vector Tile. Coordinates are given in tile space, i.e. from zero to
+/// the extent with the origin in the top left corner.
+pub struct MvtLayer {
+    name: String,
+    extent: u32,
+    features: Vec<MvtFeature>,
+    keys: Vec<String>,
+    key_indices: HashMap<String, u32>,
+    values: Vec<MvtValue>,
+    value_indices: HashMap<MvtValue, u32>,
+}
+
+impl MvtLayer {
+    pub fn new(name: &str, extent: u32) -> Self {
+        MvtLayer {
+            name: name.to_string(),
+            extent,
+            features: vec![],
+            keys: vec![],
+            key_indices: HashMap::new(),
+            values: vec![],
+            value_indices: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    pub fn add_point(&mut self, point: (i32, i32), properties: &[(&str, MvtValue)]) {
+        let tags = self.encode_tags(properties);
+        let mut geometry = vec![];
+        let mut cursor = (0, 0);
+        geometry.push(command_integer(COMMAND_MOVE_TO, 1));
+        push_delta(&mut geometry, &mut cursor, point);
+        self.features.push(MvtFeature {
+            tags,
+            geometry_type: GEOMETRY_TYPE_POINT,
+            geometry,
+        });
+    }
+
+    /// Adds a polygon that consists of an exterior ring followed by its holes. The rings must
+    /// not repeat the first point at the end. Rings are reoriented as required by the
+    /// specification and degenerate rings are skipped.
+    pub fn add_polygon(&mut self, rings: &[Vec<(i32, i32)>], properties: &[(&str, MvtValue)]) {
+        let mut geometry = vec![];
+        let mut cursor = (0, 0);
+        let mut has_exterior = false;
+        for (ring_i, ring) in rings.iter().enumerate() {
+            let mut ring = ring.clone();
+            ring.dedup();
+            let area = signed_area(&ring);
+            if ring.len() < 3 || area == 0 {
+                if ring_i == 0 {
+                    return;
+                }
+                continue;
+            }
+            // Exterior rings have a positive area in tile space (clockwise when y points down),
+            // interior rings a negative one.
+            let is_exterior = ring_i == 0;
+            if (area > 0) != is_exterior {
+                ring.reverse();
+            }
+            has_exterior = true;
+            geometry.push(command_integer(COMMAND_MOVE_TO, 1));
+            push_delta(&mut geometry, &mut cursor, ring[0]);
+            geometry.push(command_integer(COMMAND_LINE_TO, ring.len() as u32 - 1));
+            for point in &ring[1..] {
+                push_delta(&mut geometry, &mut cursor, *point);
+            }
+            geometry.push(command_integer(COMMAND_CLOSE_PATH, 1));
+        }
+        if !has_exterior {
+            return;
+        }
+        let tags = self.encode_tags(properties);
+        self.features.push(MvtFeature {
+            tags,
+            geometry_type: GEOMETRY_TYPE_POLYGON,
+            geometry,
+        });
+    }
+
+    fn encode_tags(&mut self, properties: &[(&str, MvtValue)]) -> Vec<u32> {
+        let mut tags = vec![];
+        for (key, value) in properties {
+            let key_i = *self.key_indices.entry(key.to_string()).or_insert_with(|| {
+                self.keys.push(key.to_string());
+                self.keys.len() as u32 - 1
+            });
+            let value_i = *self.value_indices.entry(value.clone()).or_insert_with(|| {
+                self.values.push(value.clone());
+                self.values.len() as u32 - 1
+            });
+            tags.push(key_i);
+            tags.push(value_i);
+        }
+        tags
+    }
+
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        write_varint_field(buffer, 15, 2);
+        write_bytes_field(buffer, 1, self.name.as_bytes());
+        for feature in &self.features {
+            let mut feature_buffer = vec![];
+            write_packed_field(&mut feature_buffer, 2, &feature.tags);
+            write_varint_field(&mut feature_buffer, 3, feature.geometry_type);
+            write_packed_field(&mut feature_buffer, 4, &feature.geometry);
+            write_bytes_field(buffer, 2, &feature_buffer);
+        }
+        for key in &self.keys {
+            write_bytes_field(buffer, 3, key.as_bytes());
+        }
+        for value in &self.values {
+            let mut value_buffer = vec![];
+            match value {
+                MvtValue::String(value) => {
+                    write_bytes_field(&mut value_buffer, 1, value.as_bytes())
+                }
+                MvtValue::UInt(value) => write_varint_field(&mut value_buffer, 5, *value),
+            }
+            write_bytes_field(buffer, 4, &value_buffer);
+        }
+        write_varint_field(buffer, 5, self.extent as u64);
+    }
+}
+
+/// Encodes the non-empty layers into a tile.
+pub fn encode_tile(layers: &[MvtLayer]) -> Vec<u8> {
+    let mut buffer = vec![];
+    for layer in layers {
+        if layer.is_empty() {
+            continue;
+        }
+        let mut layer_buffer = vec![];
+        layer.encode(&mut layer_buffer);
+        write_bytes_field(&mut buffer, 3, &layer_buffer);
+    }
+    buffer
+}
+
+/// Clips a ring to an axis aligned rectangle using the Sutherland-Hodgman algorithm.
+pub fn clip_ring(ring: &[(f64, f64)], min: f64, max: f64) -> Vec<(f64, f64)> {
+    let mut result = ring.to_vec();
+    // Each edge is given by the axis, the boundary value and whether points below it are
+    // inside.
+    for (axis, boundary, keep_below) in [
+        (0, min, false),
+        (0, max, true),
+        (1, min, false),
+        (1, max, true),
+    ] {
+        if result.is_empty() {
+            break;
+        }
+        let coordinate = |point: &(f64, f64)| if axis == 0 { point.0 } else { point.1 };
+        let is_inside = |point: &(f64, f64)| {
+            if keep_below {
+                coordinate(point) <= boundary
+            } else {
+                coordinate(point) >= boundary
+            }
+        };
+        let input = std::mem::take(&mut result);
+        for (i, current) in input.iter().enumerate() {
+            let previous = &input[(i + input.len() - 1) % input.len()];
+            let intersection = || {
+                let factor = (boundary - coordinate(previous))
+                    / (coordinate(current) - coordinate(previous));
+                (
+                    previous.0 + (current.0 - previous.0) * factor,
+                    previous.1 + (current.1 - previous.1) * factor,
+                )
+            };
+            match (is_inside(previous), is_inside(current)) {
+                (true, true) => result.push(*current),
+                (true, false) => result.push(intersection()),
+                (false, true) => {
+                    result.push(intersection());
+                    result.push(*current);
+                }
+                (false, false) => {}
+            }
+        }
+    }
+    result
+}
+
+fn signed_area(ring: &[(i32, i32)]) -> i64 {
+    let mut area = 0;
+    for (i, current) in ring.iter().enumerate() {
+        let next = &ring[(i + 1) % ring.len()];
+        area += current.0 as i64 * next.1 as i64 - next.0 as i64 * current.1 as i64;
+    }
+    area
+}
+
+fn command_integer(command: u32, count: u32) -> u32 {
+    (command & 0x7) | (count << 3)
+}
+
+fn push_delta(geometry: &mut Vec<u32>, cursor: &mut (i32, i32), point: (i32, i32)) {
+    geometry.push(zigzag(point.0 - cursor.0));
+    geometry.push(zigzag(point.1 - cursor.1));
+    *cursor = point;
+}
+
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+fn write_varint_field(buffer: &mut Vec<u8>, field: u32, value: u64) {
+    write_varint(buffer, (field as u64) << 3);
+    write_varint(buffer, value);
+}
+
+fn write_bytes_field(buffer: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_varint(buffer, ((field as u64) << 3) | 2);
+    write_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+fn write_packed_field(buffer: &mut Vec<u8>, field: u32, values: &[u32]) {
+    if values.is_empty() {
+        return;
+    }
+    let mut packed = vec![];
+    for value in values {
+        write_varint(&mut packed, *value as u64);
+    }
+    write_bytes_field(buffer, field, &packed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zigzag_interleaves_signs() {
+        assert_eq!([0, -1, 1, -2, 2].map(zigzag), [0, 1, 2, 3, 4]);
+        assert_eq!(zigzag(i32::MIN), u32::MAX);
+    }
+
+    /// Geometries from the examples of the Mapbox Vector Tile specification.
+    #[test]
+    fn encodes_geometries_like_the_specification() {
+        let mut layer = MvtLayer::new("test", DEFAULT_EXTENT);
+        layer.add_point((25, 17), &[]);
+        layer.add_polygon(&[vec![(3, 6), (8, 12), (20, 34)]], &[]);
+        assert_eq!(layer.features[0].geometry, vec![9, 50, 34]);
+        assert_eq!(layer.features[0].geometry_type, GEOMETRY_TYPE_POINT);
+        assert_eq!(
+            layer.features[1].geometry,
+            vec![9, 6, 12, 18, 10, 12, 24, 44, 15]
+        );
+        assert_eq!(layer.features[1].geometry_type, GEOMETRY_TYPE_POLYGON);
+    }
+
+    #[test]
+    fn reorients_polygon_rings() {
+        let mut layer = MvtLayer::new("test", DEFAULT_EXTENT);
+        // The exterior is given counter-clockwise and the hole clockwise (with y pointing
+        // down), the opposite of what the specification requires.
+        layer.add_polygon(
+            &[
+                vec![(0, 10), (10, 10), (10, 0), (0, 0)],
+                vec![(8, 2), (8, 8), (2, 8), (2, 2)],
+            ],
+            &[],
+        );
+        assert_eq!(
+            layer.features[0].geometry,
+            vec![
+                9, 0, 0, 26, 20, 0, 0, 20, 19, 0, 15, // Exterior, clockwise.
+                9, 4, 15, 26, 0, 12, 12, 0, 0, 11, 15, // Hole, counter-clockwise.
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_degenerate_polygons() {
+        let mut layer = MvtLayer::new("test", DEFAULT_EXTENT);
+        layer.add_polygon(&[vec![(0, 0), (5, 5), (10, 10)]], &[]);
+        assert!(layer.is_empty());
+        assert!(encode_tile(&[layer]).is_empty());
+    }
+
+    #[test]
+    fn encodes_layer_with_properties() {
+        let mut layer = MvtLayer::new("a", DEFAULT_EXTENT);
+        layer.add_point((25, 17), &[("k", MvtValue::UInt(1))]);
+        assert_eq!(
+            encode_tile(&[layer]),
+            [
+                0x1a, 28, // Layer.
+                0x78, 2, // Version.
+                0x0a, 1, b'a', // Name.
+                0x12, 11, // Feature.
+                0x12, 2, 0, 0, // Tags.
+                0x18, 1, // Point.
+                0x22, 3, 9, 50, 34, // Geometry.
+                0x1a, 1, b'k', // Key.
+                0x22, 2, 0x28, 1, // Value.
+                0x28, 0x80, 0x20, // Extent.
+            ]
+        );
+    }
+
+    #[test]
+    fn clips_ring_to_tile() {
+        let ring = [(-10.0, -10.0), (20.0, -10.0), (20.0, 20.0), (-10.0, 20.0)];
+        let clipped = clip_ring(&ring, 0.0, 10.0);
+        assert_eq!(clipped.len(), 4);
+        for corner in [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)] {
+            assert!(clipped.contains(&corner), "{:?} missing", corner);
+        }
+        assert!(clip_ring(&ring, 30.0, 40.0).is_empty());
+    }
+}
@@ -3,16 +3,19 @@ use anyhow::Result;
 use indicatif::ProgressIterator;
 use std::{
     collections::HashMap,
-    io::Write,
     path::{Path, PathBuf},
 };
 
-use crate::prepare_gtfs_as_rkyv;
+use crate::{connection_filter::TransportMode, gtfs_rkyv, prepare_gtfs_as_rkyv, station_transfers};
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
 #[rkyv(derive(Debug))]
 pub struct AllConnections {
     pub stations: Vec<ConnectionsFromStation>,
+    /// Same connections as in `stations` but with every connection reversed, i.e. the
+    /// connections of a station lead to the stations it can be reached from. This is used
+    /// for backward searches.
+    pub reversed_stations: Vec<ConnectionsFromStation>,
 }
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug, Clone)]
@@ -20,16 +23,101 @@ pub struct AllConnections {
 pub struct ConnectionsFromStation {
     pub main_stop_i: u32,
     pub connections: Vec<ConnectionToStation>,
+    /// Sorted stop indices of the platforms that have walking times from `pathways.txt`. Empty
+    /// if the station has no pathways, in which case transfers take no time.
+    pub transfer_stop_indices: Vec<u32>,
+    /// Walking times between `transfer_stop_indices` in row-major order, with
+    /// `station_transfers::NO_TRANSFER` for platforms that are not connected.
+    pub transfer_times: Vec<u32>,
+    /// Same as `transfer_times`, but without stairs and escalators.
+    pub step_free_transfer_times: Vec<u32>,
 }
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug, Copy, Clone)]
 #[rkyv(derive(Debug))]
 pub struct ConnectionToStation {
     pub to_station_i: u32,
+    /// Stop the connection departs from and arrives at. This is the platform for stations with
+    /// transfer times and the station's `main_stop_i` otherwise.
+    pub from_stop_i: u32,
+    pub to_stop_i: u32,
     pub duration: u32,
+    /// Bit of the `TransportMode` this connection is served by. Connections between the same
+    /// stations with different modes are stored separately so that they can be filtered.
+    pub route_type_mask: u16,
+    /// Index into `GtfsData.agencies` or `NO_AGENCY`. Like the mode, the agency is part of
+    /// what makes connections distinct.
+    pub agency_i: u32,
+    /// Whether the trip and both stops are wheelchair accessible. Accessible and other
+    /// connections between the same stops are stored separately.
+    pub wheelchair_accessible: bool,
 }
 
-const DIRECT_CONNECTIONS_FILE_NAME: &str = "all_connections.bin";
+/// Used for connections whose route does not reference a known agency.
+pub const NO_AGENCY: u32 = u32::MAX;
+
+impl ArchivedConnectionsFromStation {
+    /// Number of places the station can be reached at: one per platform with transfer times
+    /// and a last one for everywhere else, which is also where searches start. Searches keep
+    /// an arrival per place, since the platform decides how long the next transfer takes.
+    pub fn arrival_slots_num(&self) -> usize {
+        self.transfer_stop_indices.len() + 1
+    }
+
+    /// Place the station is reached at when arriving at the stop, see `arrival_slots_num`.
+    pub fn arrival_slot(&self, stop_i: u32) -> usize {
+        self.transfer_stop_indices
+            .binary_search_by_key(&stop_i, |transfer_stop_i| transfer_stop_i.to_native())
+            .unwrap_or(self.transfer_stop_indices.len())
+    }
+
+    /// Platform of an arrival slot, `None` for the last slot that stands for everywhere else.
+    pub fn arrival_slot_stop_i(&self, slot: usize) -> Option<u32> {
+        self.transfer_stop_indices
+            .get(slot)
+            .map(|stop_i| stop_i.to_native())
+    }
+
+    /// Time to walk from the platform a station was reached at to the platform a connection
+    /// departs from. No platform means the search started here, which needs no transfer.
+    /// Returns `None` if the platforms are only connected by stairs or escalators and
+    /// `step_free` is set.
+    pub fn transfer_time(
+        &self,
+        from_stop_i: Option<u32>,
+        to_stop_i: u32,
+        step_free: bool,
+    ) -> Option<u32> {
+        let transfer_stop_i = |stop_i: u32| {
+            self.transfer_stop_indices
+                .binary_search_by_key(&stop_i, |transfer_stop_i| transfer_stop_i.to_native())
+                .ok()
+        };
+        let (Some(from_i), Some(to_i)) = (
+            from_stop_i.and_then(transfer_stop_i),
+            transfer_stop_i(to_stop_i),
+        ) else {
+            return Some(0);
+        };
+        let times = if step_free {
+            &self.step_free_transfer_times
+        } else {
+            &self.transfer_times
+        };
+        match times[from_i * self.transfer_stop_indices.len() + to_i].to_native() {
+            station_transfers::NO_TRANSFER if step_free => None,
+            // Pathways are often incomplete, so platforms without a path are treated like
+            // stations without pathways.
+            station_transfers::NO_TRANSFER => Some(0),
+            time => Some(time),
+        }
+    }
+}
+
+/// Version of the archived `AllConnections` layout. The file name also contains the version of
+/// the GTFS data it was built from, since it refers to stops by their index in that data. Bump
+/// it whenever an archived type changes.
+const FORMAT_VERSION: u32 = 6;
 
 pub async fn load_direct_connections_rkyv(
     gtfs_folder_path: &Path,
@@ -41,12 +129,15 @@ pub async fn load_direct_connections_rkyv(
 }
 
 pub async fn ensure_direct_connections_rkyv(gtfs_folder_path: &Path) -> Result<PathBuf> {
-    let output_path = gtfs_folder_path.join(DIRECT_CONNECTIONS_FILE_NAME);
+    let output_path = gtfs_folder_path.join(format!(
+        "all_connections_v{}_{}.bin",
+        FORMAT_VERSION,
+        prepare_gtfs_as_rkyv::FORMAT_VERSION
+    ));
     if !output_path.exists() {
         let rkyv_buffer = get_direct_connections_rkyv_buffer(gtfs_folder_path).await?;
         log::info!("Writing data to {:?}", output_path);
-        let mut file = std::fs::File::create(&output_path)?;
-        file.write_all(&rkyv_buffer)?;
+        prepare_gtfs_as_rkyv::write_file_atomically(&output_path, &rkyv_buffer)?;
     }
     Ok(output_path)
 }
@@ -63,79 +154,136 @@ pub async fn get_direct_connections_rkyv_buffer(
 
     let mut connections_by_stations = vec![];
 
-    let mut station_index_by_stop_id = HashMap::new();
-    for (stop_i, stop) in src_data
+    let stop_index_by_id: HashMap<&str, usize> = src_data
+        .stops
+        .iter()
+        .enumerate()
+        .map(|(stop_i, stop)| (stop.id.as_str(), stop_i))
+        .collect();
+
+    // Every stop belongs to the station at the top of its parent chain. Only stops and stations
+    // can be at the top, so that entrances, generic nodes and boarding areas without a parent
+    // do not become stations of their own.
+    let root_stop_indices: Vec<Option<usize>> = src_data
         .stops
         .iter()
-        .into_iter()
         .enumerate()
         .progress_with_style(style.clone())
-        .with_message("Order unique stations.")
+        .with_message("Resolve parent stations.")
         .with_finish(indicatif::ProgressFinish::AndLeave)
-    {
-        if stop.parent_station_id.is_none() {
-            let station_i = station_index_by_stop_id.len() as u32;
+        .map(|(stop_i, _)| find_root_stop_i(&src_data.stops, &stop_index_by_id, stop_i))
+        .collect();
+
+    let transfers_by_root_stop_i = station_transfers::compute_station_transfers(
+        &src_data,
+        &stop_index_by_id,
+        &root_stop_indices,
+    );
+
+    let mut station_by_stop_i = vec![None; src_data.stops.len()];
+    let mut station_index_by_root_stop_i = HashMap::new();
+    let mut reversed_transfers = vec![];
+    for (stop_i, stop) in src_data.stops.iter().enumerate() {
+        if root_stop_indices[stop_i] == Some(stop_i) && stop.location_type.can_be_station() {
+            station_index_by_root_stop_i.insert(stop_i, connections_by_stations.len() as u32);
+            let transfers = transfers_by_root_stop_i.get(&stop_i);
+            reversed_transfers.push(transfers.map(|transfers| transfers.transposed()));
+            let (transfer_stop_indices, transfer_times, step_free_transfer_times) = transfers
+                .map(|transfers| {
+                    (
+                        transfers.stop_indices.clone(),
+                        transfers.times.clone(),
+                        transfers.step_free_times.clone(),
+                    )
+                })
+                .unwrap_or_default();
             connections_by_stations.push(ConnectionsFromStation {
                 main_stop_i: stop_i as u32,
                 connections: vec![],
+                transfer_stop_indices,
+                transfer_times,
+                step_free_transfer_times,
             });
-            station_index_by_stop_id.insert(stop.id.as_str(), station_i);
         }
     }
 
-    for stop in src_data
+    for (stop_i, stop) in src_data
         .stops
         .iter()
-        .into_iter()
+        .enumerate()
         .progress_with_style(style.clone())
         .with_message("Map stops to stations.")
         .with_finish(indicatif::ProgressFinish::AndLeave)
     {
-        if let Some(parent_station_id) = stop.parent_station_id.as_ref() {
-            if let Some(station_i) = station_index_by_stop_id.get(parent_station_id.as_str()) {
-                station_index_by_stop_id.insert(&stop.id.as_str(), *station_i);
-            }
+        if !stop.location_type.is_boarding_location() && root_stop_indices[stop_i] != Some(stop_i) {
+            continue;
         }
+        let Some(station_i) = root_stop_indices[stop_i]
+            .and_then(|root_stop_i| station_index_by_root_stop_i.get(&root_stop_i))
+        else {
+            continue;
+        };
+        // Connections are kept apart per platform only where walking times between the
+        // platforms are known.
+        let station = &connections_by_stations[*station_i as usize];
+        let connection_stop_i = if station.transfer_stop_indices.contains(&(stop_i as u32)) {
+            stop_i as u32
+        } else {
+            station.main_stop_i
+        };
+        station_by_stop_i[stop_i] = Some((
+            *station_i,
+            connection_stop_i,
+            is_wheelchair_boarding_possible(&src_data.stops, &stop_index_by_id, stop_i),
+        ));
     }
 
-    let mut stops_by_trip = HashMap::new();
-
-    for stop_time in src_data
-        .stop_times
-        .iter()
-        .progress_with_style(style.clone())
-        .with_message("Find stop times for each trip.")
-        .with_finish(indicatif::ProgressFinish::AndLeave)
-    {
-        let stops_in_trip = stops_by_trip
-            .entry(&stop_time.trip_id)
-            .or_insert_with(|| vec![]);
-        stops_in_trip.push(stop_time);
-    }
+    let route_info_by_trip_i = route_info_by_trip_i(&src_data);
+    let stop_times = &src_data.stop_times;
 
     let mut shortest_durations = HashMap::new();
 
-    for item in stops_by_trip
-        .iter_mut()
+    for trip_i in (0..src_data.trips.len())
         .progress_with_style(style.clone())
         .with_message("Find shortest durations.")
         .with_finish(indicatif::ProgressFinish::AndLeave)
     {
-        let stops_in_trip = item.1;
-        stops_in_trip.sort_by_key(|stop_time| stop_time.stop_sequence);
+        let (mode, agency_i) = route_info_by_trip_i[trip_i];
+        let route_type_mask = mode.mask_bit();
+        let trip_wheelchair_accessible = matches!(
+            src_data.trips[trip_i].wheelchair_accessible,
+            gtfs_rkyv::ArchivedGtfsAvailability::Available
+        );
 
-        for connection in stops_in_trip.windows(2) {
-            if let (Some(from_station_i), Some(to_station_i)) = (
-                station_index_by_stop_id.get(connection[0].stop_id.as_str()),
-                station_index_by_stop_id.get(connection[1].stop_id.as_str()),
+        let stop_time_range = stop_times.trip_range(trip_i);
+        for stop_time_i in stop_time_range.start..stop_time_range.end.saturating_sub(1) {
+            if let (
+                Some((from_station_i, from_stop_i, from_wheelchair_boarding)),
+                Some((to_station_i, to_stop_i, to_wheelchair_boarding)),
+            ) = (
+                &station_by_stop_i[stop_times.stop_i(stop_time_i)],
+                &station_by_stop_i[stop_times.stop_i(stop_time_i + 1)],
             ) {
                 if let (Some(deparature_time), Some(arrival_time)) = (
-                    connection[0].departure_time.as_ref(),
-                    connection[1].arrival_time.as_ref(),
+                    stop_times.departure_time(stop_time_i),
+                    stop_times.arrival_time(stop_time_i + 1),
                 ) {
-                    let duration = arrival_time - deparature_time;
+                    // Backwards times are reported by the validation and skipped here.
+                    let Some(duration) = arrival_time.checked_sub(deparature_time) else {
+                        continue;
+                    };
                     let entry = shortest_durations
-                        .entry((*from_station_i, *to_station_i))
+                        .entry((
+                            *from_station_i,
+                            *to_station_i,
+                            *from_stop_i,
+                            *to_stop_i,
+                            route_type_mask,
+                            agency_i,
+                            trip_wheelchair_accessible
+                                && *from_wheelchair_boarding
+                                && *to_wheelchair_boarding,
+                        ))
                         .or_insert(duration);
                     if *entry > duration {
                         *entry = duration;
@@ -145,7 +293,29 @@ pub async fn get_direct_connections_rkyv_buffer(
         }
     }
 
-    for ((from_station_i, to_station_i), duration) in shortest_durations
+    let mut reversed_connections_by_stations = connections_by_stations.clone();
+    for (reversed_station, transfers) in reversed_connections_by_stations
+        .iter_mut()
+        .zip(reversed_transfers)
+    {
+        if let Some(transfers) = transfers {
+            reversed_station.transfer_times = transfers.times;
+            reversed_station.step_free_transfer_times = transfers.step_free_times;
+        }
+    }
+
+    for (
+        (
+            from_station_i,
+            to_station_i,
+            from_stop_i,
+            to_stop_i,
+            route_type_mask,
+            agency_i,
+            wheelchair_accessible,
+        ),
+        duration,
+    ) in shortest_durations
         .iter()
         .progress_with_style(style.clone())
         .with_message("Create connections.")
@@ -155,11 +325,127 @@ pub async fn get_direct_connections_rkyv_buffer(
             .connections
             .push(ConnectionToStation {
                 to_station_i: *to_station_i,
+                from_stop_i: *from_stop_i,
+                to_stop_i: *to_stop_i,
+                duration: *duration,
+                route_type_mask: *route_type_mask,
+                agency_i: *agency_i,
+                wheelchair_accessible: *wheelchair_accessible,
+            });
+        reversed_connections_by_stations[*to_station_i as usize]
+            .connections
+            .push(ConnectionToStation {
+                to_station_i: *from_station_i,
+                from_stop_i: *to_stop_i,
+                to_stop_i: *from_stop_i,
                 duration: *duration,
+                route_type_mask: *route_type_mask,
+                agency_i: *agency_i,
+                wheelchair_accessible: *wheelchair_accessible,
             });
     }
 
-    return Ok(rkyv::to_bytes::<rkyv::rancor::Error>(&AllConnections {
+    Ok(rkyv::to_bytes::<rkyv::rancor::Error>(&AllConnections {
         stations: connections_by_stations,
-    })?);
+        reversed_stations: reversed_connections_by_stations,
+    })?)
+}
+
+/// Mode and agency index of every route.
+pub fn route_info_by_route_i(src_data: &gtfs_rkyv::ArchivedGtfsData) -> Vec<(TransportMode, u32)> {
+    let mut agency_index_by_id = HashMap::new();
+    for (agency_i, agency) in src_data.agencies.iter().enumerate() {
+        if let Some(agency_id) = agency.id.as_ref() {
+            agency_index_by_id.insert(agency_id.as_str(), agency_i as u32);
+        }
+    }
+
+    src_data
+        .routes
+        .iter()
+        .map(|route| {
+            let agency_i = match route.agency_id.as_ref() {
+                Some(agency_id) => agency_index_by_id
+                    .get(agency_id.as_str())
+                    .copied()
+                    .unwrap_or(NO_AGENCY),
+                // The agency is optional if there is only one.
+                None if src_data.agencies.len() == 1 => 0,
+                None => NO_AGENCY,
+            };
+            (TransportMode::from_route(route), agency_i)
+        })
+        .collect()
+}
+
+/// Mode and agency index of the route of every trip. Trips whose route does not exist get
+/// `(TransportMode::Other, NO_AGENCY)`.
+pub fn route_info_by_trip_i(src_data: &gtfs_rkyv::ArchivedGtfsData) -> Vec<(TransportMode, u32)> {
+    let route_info_by_route_i = route_info_by_route_i(src_data);
+    let route_index_by_id: HashMap<&str, usize> = src_data
+        .routes
+        .iter()
+        .enumerate()
+        .map(|(route_i, route)| (route.id.as_str(), route_i))
+        .collect();
+
+    src_data
+        .trips
+        .iter()
+        .map(|trip| {
+            route_index_by_id
+                .get(trip.route_id.as_str())
+                .map(|route_i| route_info_by_route_i[*route_i])
+                .unwrap_or((TransportMode::Other, NO_AGENCY))
+        })
+        .collect()
+}
+
+/// Follows the parent stations up to the stop without a parent. A parent that does not exist
+/// ends the chain. Returns `None` for stops that are part of a parent station cycle.
+pub fn find_root_stop_i(
+    stops: &[gtfs_rkyv::ArchivedGtfsStop],
+    stop_index_by_id: &HashMap<&str, usize>,
+    stop_i: usize,
+) -> Option<usize> {
+    let mut current_stop_i = stop_i;
+    // A chain can not be longer than the number of stops without containing a cycle.
+    for _ in 0..stops.len() {
+        let parent_stop_i = stops[current_stop_i]
+            .parent_station_id
+            .as_ref()
+            .and_then(|parent_station_id| stop_index_by_id.get(parent_station_id.as_str()));
+        match parent_stop_i {
+            Some(parent_stop_i) => current_stop_i = *parent_stop_i,
+            None => return Some(current_stop_i),
+        }
+    }
+    None
+}
+
+/// Whether wheelchair users can board at the stop. Platforms without information inherit the
+/// accessibility of their parent station.
+pub fn is_wheelchair_boarding_possible(
+    stops: &[gtfs_rkyv::ArchivedGtfsStop],
+    stop_index_by_id: &HashMap<&str, usize>,
+    stop_i: usize,
+) -> bool {
+    let mut current_stop_i = stop_i;
+    for _ in 0..stops.len() {
+        let stop = &stops[current_stop_i];
+        match stop.wheelchair_boarding {
+            gtfs_rkyv::ArchivedGtfsAvailability::Available => return true,
+            gtfs_rkyv::ArchivedGtfsAvailability::InformationNotAvailable => {}
+            _ => return false,
+        }
+        match stop
+            .parent_station_id
+            .as_ref()
+            .and_then(|parent_station_id| stop_index_by_id.get(parent_station_id.as_str()))
+        {
+            Some(parent_stop_i) => current_stop_i = *parent_stop_i,
+            None => return false,
+        }
+    }
+    false
 }
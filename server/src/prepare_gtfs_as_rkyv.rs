@@ -1,15 +1,22 @@
 use std::{
+    collections::HashMap,
     io::Write,
     path::{Path, PathBuf},
 };
 
 use crate::{
     gtfs_rkyv::{self, *},
+    interpolate_stop_times,
     memory_mapped_rkyv::{self, MemoryMappedRkyv},
+    services, shapes, stop_times,
+    validate_gtfs::{self, Severity},
 };
 use anyhow::Result;
 
-const RKYV_FILE_NAME: &str = "data_rkyv.bin";
+/// Version of the archived `GtfsData` layout. It is part of the file name, so that files
+/// written by an older version are prepared again instead of being read with the wrong layout.
+/// Bump it whenever an archived type changes.
+pub const FORMAT_VERSION: u32 = 16;
 
 pub async fn load_gtfs_folder_rkyv(
     gtfs_folder_path: &Path,
@@ -21,20 +28,41 @@ pub async fn load_gtfs_folder_rkyv(
 }
 
 pub async fn ensure_gtfs_folder_rkyv(gtfs_folder_path: &Path) -> Result<PathBuf> {
-    let output_path = gtfs_folder_path.join(RKYV_FILE_NAME);
+    let output_path = gtfs_folder_path.join(format!("data_rkyv_v{}.bin", FORMAT_VERSION));
     if !output_path.exists() {
         let rkyv_buffer = gtfs_data_to_rkyv_buffer(gtfs_folder_path)?;
         log::info!("Writing data to {:?}", output_path);
-        let mut file = std::fs::File::create(&output_path)?;
-        file.write_all(&rkyv_buffer)?;
+        write_file_atomically(&output_path, &rkyv_buffer)?;
     }
     Ok(output_path)
 }
 
+/// Writes to a temporary file first, so that an interrupted run does not leave a truncated
+/// archive behind that would be memory mapped by the next run.
+pub fn write_file_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let temporary_path = path.with_extension("tmp");
+    let mut file = std::fs::File::create(&temporary_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    std::fs::rename(&temporary_path, path)?;
+    Ok(())
+}
+
 fn gtfs_data_to_rkyv_buffer(gtfs_folder_path: &Path) -> Result<rkyv::util::AlignedVec> {
     log::info!("Loading original GTFS data from {:?}", gtfs_folder_path);
     let gtfs = gtfs_structures::RawGtfs::from_path(gtfs_folder_path)?;
 
+    let report = validate_gtfs::validate_raw_gtfs(&gtfs);
+    for summary in &report.summary {
+        match summary.severity {
+            Severity::Error => log::error!("{:?}: {}", summary.kind, summary.count),
+            Severity::Warning => log::warn!("{:?}: {}", summary.kind, summary.count),
+        }
+    }
+    if !report.summary.is_empty() {
+        log::warn!("The feed has issues, run the validate command for a detailed report");
+    }
+
     log::info!("Preparing stops...");
     let mut gtfs_stops = vec![];
     for stop in gtfs.stops? {
@@ -45,21 +73,20 @@ fn gtfs_data_to_rkyv_buffer(gtfs_folder_path: &Path) -> Result<rkyv::util::Align
             parent_station_id: stop.parent_station.clone(),
             latitude: stop.latitude,
             longitude: stop.longitude,
+            location_type: match stop.location_type {
+                gtfs_structures::LocationType::StopPoint => GtfsLocationType::StopPoint,
+                gtfs_structures::LocationType::StopArea => GtfsLocationType::Station,
+                gtfs_structures::LocationType::StationEntrance => GtfsLocationType::StationEntrance,
+                gtfs_structures::LocationType::GenericNode => GtfsLocationType::GenericNode,
+                gtfs_structures::LocationType::BoardingArea => GtfsLocationType::BoardingArea,
+                gtfs_structures::LocationType::Unknown(other) => GtfsLocationType::Other(other),
+            },
+            level_id: stop.level_id.clone(),
+            wheelchair_boarding: gtfs_availability(stop.wheelchair_boarding),
+            timezone: stop.timezone.clone(),
         });
     }
 
-    log::info!("Preparing stop times.");
-    let mut gtfs_stop_times = vec![];
-    for stop_time in gtfs.stop_times? {
-        gtfs_stop_times.push(GtfsStopTime {
-            arrival_time: stop_time.arrival_time,
-            departure_time: stop_time.departure_time,
-            stop_id: stop_time.stop_id.clone(),
-            stop_sequence: stop_time.stop_sequence,
-            trip_id: stop_time.trip_id.clone(),
-        })
-    }
-
     log::info!("Preparing trips.");
     let mut gtfs_trips = vec![];
     for trip in gtfs.trips? {
@@ -68,16 +95,69 @@ fn gtfs_data_to_rkyv_buffer(gtfs_folder_path: &Path) -> Result<rkyv::util::Align
             service_id: trip.service_id.clone(),
             route_id: trip.route_id.clone(),
             short_name: trip.trip_short_name.clone(),
+            wheelchair_accessible: gtfs_availability(trip.wheelchair_accessible),
+            shape_id: trip.shape_id.clone(),
         });
     }
 
+    log::info!("Preparing stop times.");
+    let stop_index_by_id: HashMap<&str, u32> = gtfs_stops
+        .iter()
+        .enumerate()
+        .map(|(stop_i, stop)| (stop.id.as_str(), stop_i as u32))
+        .collect();
+    let trip_index_by_id: HashMap<&str, u32> = gtfs_trips
+        .iter()
+        .enumerate()
+        .map(|(trip_i, trip)| (trip.id.as_str(), trip_i as u32))
+        .collect();
+    let mut gtfs_stop_times = vec![];
+    let mut unknown_reference_num = 0;
+    for stop_time in gtfs.stop_times? {
+        let (Some(stop_i), Some(trip_i)) = (
+            stop_index_by_id.get(stop_time.stop_id.as_str()),
+            trip_index_by_id.get(stop_time.trip_id.as_str()),
+        ) else {
+            unknown_reference_num += 1;
+            continue;
+        };
+        gtfs_stop_times.push(GtfsStopTime {
+            arrival_time: stop_time.arrival_time,
+            departure_time: stop_time.departure_time,
+            stop_i: *stop_i,
+            stop_sequence: stop_time.stop_sequence,
+            trip_i: *trip_i,
+            shape_dist_traveled: stop_time.shape_dist_traveled,
+            interpolated: false,
+        })
+    }
+    if unknown_reference_num > 0 {
+        log::warn!(
+            "Skipped {} stop times with an unknown stop or trip",
+            unknown_reference_num
+        );
+    }
+
+    log::info!("Interpolating untimed stop times.");
+    let interpolated_num =
+        interpolate_stop_times::interpolate_stop_times(&mut gtfs_stop_times, &gtfs_stops);
+    log::info!("Interpolated {} stop times.", interpolated_num);
+    let gtfs_stop_times = stop_times::build_stop_times(gtfs_stop_times, gtfs_trips.len());
+
     log::info!("Preparing routes.");
+    let route_records = read_route_records(gtfs_folder_path)?;
     let mut gtfs_routes = vec![];
     for route in gtfs.routes? {
+        let route_record = route_records
+            .get(route.id.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Route {:?} is not in routes.txt", route.id))?;
+        let color = |r, g, b| u32::from_be_bytes([0, r, g, b]);
         gtfs_routes.push(GtfsRoute {
             id: route.id.clone(),
             short_name: route.short_name.clone(),
             long_name: route.long_name.clone(),
+            description: route.desc.clone(),
+            url: route.url.clone(),
             route_type: match route.route_type {
                 gtfs_structures::RouteType::Tramway => GtfsRouteType::Tramway,
                 gtfs_structures::RouteType::Subway => GtfsRouteType::Subway,
@@ -92,7 +172,17 @@ fn gtfs_data_to_rkyv_buffer(gtfs_folder_path: &Path) -> Result<rkyv::util::Align
                 gtfs_structures::RouteType::Taxi => GtfsRouteType::Taxi,
                 gtfs_structures::RouteType::Other(other) => GtfsRouteType::Other(other),
             },
+            route_type_code: route_record.route_type,
             agency_id: route.agency_id.clone(),
+            color: route_record
+                .route_color
+                .as_ref()
+                .map(|_| color(route.color.r, route.color.g, route.color.b)),
+            text_color: route_record
+                .route_text_color
+                .as_ref()
+                .map(|_| color(route.text_color.r, route.text_color.g, route.text_color.b)),
+            sort_order: route.order,
         });
     }
 
@@ -102,39 +192,45 @@ fn gtfs_data_to_rkyv_buffer(gtfs_folder_path: &Path) -> Result<rkyv::util::Align
         gtfs_agencies.push(GtfsAgency {
             id: agency.id.clone(),
             name: agency.name.clone(),
+            timezone: agency.timezone.clone(),
         });
     }
 
-    log::info!("Preparing calendars.");
-    let mut gtfs_calendars = vec![];
-    for calendar in gtfs.calendar.unwrap()? {
-        gtfs_calendars.push(GtfsCalendar {
-            id: calendar.id.clone(),
-            monday: calendar.monday,
-            tuesday: calendar.tuesday,
-            wednesday: calendar.wednesday,
-            thursday: calendar.thursday,
-            friday: calendar.friday,
-            saturday: calendar.saturday,
-            sunday: calendar.sunday,
-            start_date: calendar.start_date.to_string(),
-            end_date: calendar.end_date.to_string(),
+    log::info!("Preparing services.");
+    let calendars = gtfs.calendar.transpose()?.unwrap_or_default();
+    let calendar_dates = gtfs.calendar_dates.transpose()?.unwrap_or_default();
+    let gtfs_services = services::build_services(&calendars, &calendar_dates);
+
+    log::info!("Preparing pathways.");
+    let mut gtfs_pathways = vec![];
+    for pathway in gtfs.pathways.transpose()?.unwrap_or_default() {
+        gtfs_pathways.push(GtfsPathway {
+            from_stop_id: pathway.from_stop_id.clone(),
+            to_stop_id: pathway.to_stop_id.clone(),
+            mode: match pathway.mode {
+                gtfs_structures::PathwayMode::Walkway => GtfsPathwayMode::Walkway,
+                gtfs_structures::PathwayMode::Stairs => GtfsPathwayMode::Stairs,
+                gtfs_structures::PathwayMode::MovingSidewalk => GtfsPathwayMode::MovingSidewalk,
+                gtfs_structures::PathwayMode::Escalator => GtfsPathwayMode::Escalator,
+                gtfs_structures::PathwayMode::Elevator => GtfsPathwayMode::Elevator,
+                gtfs_structures::PathwayMode::FareGate => GtfsPathwayMode::FareGate,
+                gtfs_structures::PathwayMode::ExitGate => GtfsPathwayMode::ExitGate,
+            },
+            is_bidirectional: matches!(
+                pathway.is_bidirectional,
+                gtfs_structures::PathwayDirectionType::Bidirectional
+            ),
+            length: pathway.length,
+            traversal_time: pathway.traversal_time,
+            stair_count: pathway.stair_count,
         });
     }
 
-    let mut gtfs_calendar_dates = vec![];
-    if let Some(calender_dates) = gtfs.calendar_dates {
-        for calendar_date in calender_dates? {
-            gtfs_calendar_dates.push(GtfsCalendarDate {
-                service_id: calendar_date.service_id.clone(),
-                date: calendar_date.date.to_string(),
-                exception_type: match calendar_date.exception_type {
-                    gtfs_structures::Exception::Added => GtfsExceptionType::Added,
-                    gtfs_structures::Exception::Deleted => GtfsExceptionType::Deleted,
-                },
-            });
-        }
-    }
+    log::info!("Preparing shapes.");
+    let gtfs_shapes = shapes::build_shapes(gtfs.shapes.transpose()?.unwrap_or_default());
+
+    log::info!("Preparing levels.");
+    let gtfs_levels = read_levels(gtfs_folder_path)?;
 
     log::info!("Serializing data.");
     let buffer = rkyv::to_bytes::<rkyv::rancor::Error>(&GtfsData {
@@ -142,9 +238,75 @@ fn gtfs_data_to_rkyv_buffer(gtfs_folder_path: &Path) -> Result<rkyv::util::Align
         stop_times: gtfs_stop_times,
         trips: gtfs_trips,
         routes: gtfs_routes,
+        shapes: gtfs_shapes,
         agencies: gtfs_agencies,
-        calendars: gtfs_calendars,
-        calendar_dates: gtfs_calendar_dates,
+        services: gtfs_services,
+        pathways: gtfs_pathways,
+        levels: gtfs_levels,
     })?;
     Ok(buffer)
 }
+
+#[derive(Debug, serde::Deserialize)]
+struct LevelRecord {
+    level_id: String,
+    level_index: f64,
+    level_name: Option<String>,
+}
+
+/// `gtfs_structures` does not read `levels.txt`, so it is parsed here. Levels are optional and
+/// only read from unpacked feeds.
+fn read_levels(gtfs_folder_path: &Path) -> Result<Vec<GtfsLevel>> {
+    let levels_path = gtfs_folder_path.join("levels.txt");
+    if !levels_path.is_file() {
+        return Ok(vec![]);
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(levels_path)?;
+    let mut levels = vec![];
+    for record in reader.deserialize() {
+        let record: LevelRecord = record?;
+        levels.push(GtfsLevel {
+            id: record.level_id,
+            index: record.level_index,
+            name: record.level_name.filter(|name| !name.is_empty()),
+        });
+    }
+    Ok(levels)
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RouteRecord {
+    route_id: String,
+    route_type: i16,
+    #[serde(default)]
+    route_color: Option<String>,
+    #[serde(default)]
+    route_text_color: Option<String>,
+}
+
+/// `gtfs_structures` merges the extended route types into the basic ones and fills in default
+/// colors, so the original values are read from `routes.txt` again.
+fn read_route_records(gtfs_folder_path: &Path) -> Result<HashMap<String, RouteRecord>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(gtfs_folder_path.join("routes.txt"))?;
+    let mut route_records = HashMap::new();
+    for record in reader.deserialize() {
+        let record: RouteRecord = record?;
+        route_records.insert(record.route_id.clone(), record);
+    }
+    Ok(route_records)
+}
+
+fn gtfs_availability(availability: gtfs_structures::Availability) -> GtfsAvailability {
+    match availability {
+        gtfs_structures::Availability::InformationNotAvailable => {
+            GtfsAvailability::InformationNotAvailable
+        }
+        gtfs_structures::Availability::Available => GtfsAvailability::Available,
+        gtfs_structures::Availability::NotAvailable => GtfsAvailability::NotAvailable,
+        gtfs_structures::Availability::Unknown(other) => GtfsAvailability::Other(other),
+    }
+}
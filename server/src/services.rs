@@ -0,0 +1,220 @@
+use chrono::{Datelike, NaiveDate};
+use std::collections::BTreeMap;
+
+use crate::gtfs_rkyv::{ArchivedGtfsService, GtfsService};
+
+/// Days are stored as the number of days since the common era, which makes them cheap to
+/// compare and to archive.
+pub fn day_number(date: NaiveDate) -> i32 {
+    date.num_days_from_ce()
+}
+
+pub fn date_from_day_number(day: i32) -> Option<NaiveDate> {
+    NaiveDate::from_num_days_from_ce_opt(day)
+}
+
+/// Combines `calendar.txt` and `calendar_dates.txt` into one service per service id. Feeds may
+/// leave out `calendar.txt` and define all services by their exceptions, in which case the
+/// services have no regular weekdays.
+pub fn build_services(
+    calendars: &[gtfs_structures::Calendar],
+    calendar_dates: &[gtfs_structures::CalendarDate],
+) -> Vec<GtfsService> {
+    let mut services_by_id = BTreeMap::new();
+    for calendar in calendars {
+        let weekdays = [
+            calendar.monday,
+            calendar.tuesday,
+            calendar.wednesday,
+            calendar.thursday,
+            calendar.friday,
+            calendar.saturday,
+            calendar.sunday,
+        ]
+        .iter()
+        .enumerate()
+        .filter(|(_, runs)| **runs)
+        .fold(0, |weekdays, (weekday_i, _)| weekdays | (1 << weekday_i));
+        services_by_id.insert(
+            calendar.id.as_str(),
+            GtfsService {
+                id: calendar.id.clone(),
+                weekdays,
+                start_day: day_number(calendar.start_date),
+                end_day: day_number(calendar.end_date),
+                added_days: vec![],
+                removed_days: vec![],
+            },
+        );
+    }
+
+    for calendar_date in calendar_dates {
+        let day = day_number(calendar_date.date);
+        let service = services_by_id
+            .entry(calendar_date.service_id.as_str())
+            .or_insert_with(|| GtfsService {
+                id: calendar_date.service_id.clone(),
+                weekdays: 0,
+                start_day: day,
+                end_day: day,
+                added_days: vec![],
+                removed_days: vec![],
+            });
+        match calendar_date.exception_type {
+            gtfs_structures::Exception::Added => {
+                service.added_days.push(day);
+                // The weekdays of calendar services only apply within the calendar's range,
+                // added days outside of it are covered by `added_days` alone.
+                if service.weekdays == 0 {
+                    service.start_day = service.start_day.min(day);
+                    service.end_day = service.end_day.max(day);
+                }
+            }
+            gtfs_structures::Exception::Deleted => service.removed_days.push(day),
+        }
+    }
+
+    let mut services: Vec<GtfsService> = services_by_id.into_values().collect();
+    for service in &mut services {
+        service.added_days.sort_unstable();
+        service.added_days.dedup();
+        service.removed_days.sort_unstable();
+        service.removed_days.dedup();
+    }
+    services
+}
+
+impl ArchivedGtfsService {
+    /// Whether trips of this service run on the given day. Removed days take precedence over
+    /// everything else.
+    pub fn runs_on(&self, date: NaiveDate) -> bool {
+        let day = day_number(date);
+        let contains = |days: &rkyv::vec::ArchivedVec<rkyv::rend::i32_le>| {
+            days.binary_search_by_key(&day, |day| day.to_native())
+                .is_ok()
+        };
+        if contains(&self.removed_days) {
+            return false;
+        }
+        if contains(&self.added_days) {
+            return true;
+        }
+        let weekday_i = date.weekday().num_days_from_monday();
+        (self.start_day.to_native()..=self.end_day.to_native()).contains(&day)
+            && self.weekdays & (1 << weekday_i) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn weekday_calendar(
+        id: &str,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+    ) -> gtfs_structures::Calendar {
+        gtfs_structures::Calendar {
+            id: id.to_string(),
+            monday: true,
+            tuesday: true,
+            wednesday: true,
+            thursday: true,
+            friday: true,
+            saturday: false,
+            sunday: false,
+            start_date,
+            end_date,
+        }
+    }
+
+    fn calendar_date(
+        service_id: &str,
+        date: NaiveDate,
+        exception_type: gtfs_structures::Exception,
+    ) -> gtfs_structures::CalendarDate {
+        gtfs_structures::CalendarDate {
+            service_id: service_id.to_string(),
+            date,
+            exception_type,
+        }
+    }
+
+    /// Whether the first service runs on each of the dates, checked on the archived service.
+    fn runs_on(services: Vec<GtfsService>, dates: &[NaiveDate]) -> Vec<bool> {
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&services).unwrap();
+        let services =
+            rkyv::access::<rkyv::Archived<Vec<GtfsService>>, rkyv::rancor::Error>(&bytes).unwrap();
+        dates
+            .iter()
+            .map(|date| services[0].runs_on(*date))
+            .collect()
+    }
+
+    #[test]
+    fn added_day_does_not_extend_calendar_weekdays() {
+        let services = build_services(
+            &[weekday_calendar("WD", date(2024, 1, 1), date(2024, 3, 31))],
+            &[calendar_date(
+                "WD",
+                date(2024, 6, 12),
+                gtfs_structures::Exception::Added,
+            )],
+        );
+        assert_eq!(
+            runs_on(
+                services,
+                &[
+                    // Tuesday and Saturday within the calendar range.
+                    date(2024, 1, 9),
+                    date(2024, 1, 13),
+                    // The added Wednesday.
+                    date(2024, 6, 12),
+                    // Weekdays between the calendar end and the added day.
+                    date(2024, 4, 2),
+                    date(2024, 6, 11),
+                ]
+            ),
+            [true, false, true, false, false]
+        );
+    }
+
+    #[test]
+    fn removed_day_takes_precedence() {
+        let services = build_services(
+            &[weekday_calendar("WD", date(2024, 1, 1), date(2024, 3, 31))],
+            &[
+                calendar_date("WD", date(2024, 1, 9), gtfs_structures::Exception::Deleted),
+                calendar_date("WD", date(2024, 1, 9), gtfs_structures::Exception::Added),
+            ],
+        );
+        assert_eq!(
+            runs_on(services, &[date(2024, 1, 9), date(2024, 1, 10)]),
+            [false, true]
+        );
+    }
+
+    #[test]
+    fn calendar_dates_only_service_runs_on_added_days() {
+        let services = build_services(
+            &[],
+            &[
+                calendar_date("X", date(2024, 5, 1), gtfs_structures::Exception::Added),
+                calendar_date("X", date(2024, 5, 20), gtfs_structures::Exception::Added),
+            ],
+        );
+        assert_eq!(services[0].start_day, day_number(date(2024, 5, 1)));
+        assert_eq!(services[0].end_day, day_number(date(2024, 5, 20)));
+        assert_eq!(
+            runs_on(
+                services,
+                &[date(2024, 5, 1), date(2024, 5, 2), date(2024, 5, 20)]
+            ),
+            [true, false, true]
+        );
+    }
+}
@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+
+use crate::gtfs_rkyv::{
+    ArchivedGtfsData, ArchivedGtfsShape, ArchivedGtfsStop, ArchivedGtfsStopTimes, GtfsShape,
+    GtfsShapePoint,
+};
+
+/// Groups the points of `shapes.txt` into one shape per id.
+pub fn build_shapes(mut shape_points: Vec<gtfs_structures::Shape>) -> Vec<GtfsShape> {
+    shape_points.sort_by(|a, b| a.id.cmp(&b.id).then(a.sequence.cmp(&b.sequence)));
+    shape_points
+        .chunk_by(|a, b| a.id == b.id)
+        .map(|points| GtfsShape {
+            id: points[0].id.clone(),
+            points: points
+                .iter()
+                .map(|point| GtfsShapePoint {
+                    latitude: point.latitude,
+                    longitude: point.longitude,
+                    dist_traveled: point.dist_traveled,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+impl ArchivedGtfsData {
+    pub fn shape(&self, shape_id: &str) -> Option<&ArchivedGtfsShape> {
+        self.shapes
+            .binary_search_by(|shape| shape.id.as_str().cmp(shape_id))
+            .ok()
+            .map(|shape_i| &self.shapes[shape_i])
+    }
+}
+
+/// A stop of a trip as far as locating it on the trip's shape is concerned.
+#[derive(Debug, Clone, Copy)]
+pub struct ShapeStop {
+    /// `[longitude, latitude]`
+    pub coordinates: Option<[f64; 2]>,
+    pub dist_traveled: Option<f32>,
+}
+
+impl ShapeStop {
+    /// Platforms without coordinates use the coordinates of their parent station.
+    pub fn new(
+        stops: &[ArchivedGtfsStop],
+        stop_index_by_id: &HashMap<&str, usize>,
+        stop_times: &ArchivedGtfsStopTimes,
+        stop_time_i: usize,
+    ) -> ShapeStop {
+        let stop_coordinates = |stop: &ArchivedGtfsStop| {
+            Some([
+                stop.longitude.as_ref()?.to_native(),
+                stop.latitude.as_ref()?.to_native(),
+            ])
+        };
+        let stop = &stops[stop_times.stop_i(stop_time_i)];
+        let coordinates = stop_coordinates(stop).or_else(|| {
+            let parent_station_id = stop.parent_station_id.as_ref()?;
+            stop_coordinates(&stops[*stop_index_by_id.get(parent_station_id.as_str())?])
+        });
+        ShapeStop {
+            coordinates,
+            dist_traveled: stop_times.shape_dist_traveled[stop_time_i]
+                .as_ref()
+                .map(|distance| distance.to_native()),
+        }
+    }
+}
+
+/// Positions on a shape are given as fractional point indices, i.e. `2.5` is halfway between the
+/// third and fourth point.
+impl ArchivedGtfsShape {
+    /// Locates the stops of a trip on the shape. Stops are matched by `shape_dist_traveled` if
+    /// both the stop and the shape have it, and otherwise by projecting the stop onto the part
+    /// of the shape after the previous stop, so that shapes that pass a place twice work.
+    pub fn locate_stops(&self, stops: &[ShapeStop]) -> Vec<Option<f64>> {
+        let has_distances = self
+            .points
+            .iter()
+            .all(|point| point.dist_traveled.is_some());
+        let mut previous_position = 0.0;
+        stops
+            .iter()
+            .map(|stop| {
+                let position = stop
+                    .dist_traveled
+                    .filter(|_| has_distances)
+                    .map(|dist_traveled| self.position_at_distance(dist_traveled))
+                    .or_else(|| {
+                        stop.coordinates
+                            .and_then(|coordinates| self.project(coordinates, previous_position))
+                    })?;
+                previous_position = position.max(previous_position);
+                Some(position)
+            })
+            .collect()
+    }
+
+    /// Part of the shape between two positions, including the interpolated end points.
+    pub fn slice(&self, from_position: f64, to_position: f64) -> Vec<[f64; 2]> {
+        let (from_position, to_position) = if from_position <= to_position {
+            (from_position, to_position)
+        } else {
+            (to_position, from_position)
+        };
+        let mut coordinates = vec![self.coordinates_at(from_position)];
+        let first_point_i = from_position.floor() as usize + 1;
+        let last_point_i = (to_position.ceil() as usize).min(self.points.len());
+        for point_i in first_point_i..last_point_i {
+            coordinates.push(self.point_coordinates(point_i));
+        }
+        let to_coordinates = self.coordinates_at(to_position);
+        if coordinates.last() != Some(&to_coordinates) {
+            coordinates.push(to_coordinates);
+        }
+        coordinates
+    }
+
+    fn point_coordinates(&self, point_i: usize) -> [f64; 2] {
+        let point = &self.points[point_i];
+        [point.longitude.to_native(), point.latitude.to_native()]
+    }
+
+    fn coordinates_at(&self, position: f64) -> [f64; 2] {
+        let last_point_i = self.points.len() - 1;
+        let point_i = (position.floor() as usize).min(last_point_i);
+        let fraction = position - point_i as f64;
+        let from = self.point_coordinates(point_i);
+        if point_i == last_point_i || fraction <= 0.0 {
+            return from;
+        }
+        let to = self.point_coordinates(point_i + 1);
+        [
+            from[0] + (to[0] - from[0]) * fraction,
+            from[1] + (to[1] - from[1]) * fraction,
+        ]
+    }
+
+    /// Expects every point of the shape to have a `dist_traveled`.
+    fn position_at_distance(&self, dist_traveled: f32) -> f64 {
+        let distance = |point_i: usize| {
+            self.points[point_i]
+                .dist_traveled
+                .as_ref()
+                .map_or(0.0, |distance| distance.to_native())
+        };
+        let next_point_i = self.points.partition_point(|point| {
+            point
+                .dist_traveled
+                .as_ref()
+                .is_some_and(|distance| distance.to_native() <= dist_traveled)
+        });
+        if next_point_i == 0 {
+            return 0.0;
+        }
+        if next_point_i == self.points.len() {
+            return (self.points.len() - 1) as f64;
+        }
+        let point_i = next_point_i - 1;
+        let segment_length = distance(next_point_i) - distance(point_i);
+        let fraction = (dist_traveled - distance(point_i)) / segment_length;
+        point_i as f64 + fraction as f64
+    }
+
+    /// Closest position to the coordinates that is not before `min_position`. Longitudes are
+    /// scaled by the cosine of the latitude, which is precise enough for the short distances
+    /// between a stop and its shape.
+    fn project(&self, coordinates: [f64; 2], min_position: f64) -> Option<f64> {
+        if self.points.is_empty() {
+            return None;
+        }
+        if self.points.len() == 1 {
+            return Some(0.0);
+        }
+        let longitude_scale = coordinates[1].to_radians().cos();
+        let to_plane = |coordinates: [f64; 2]| [coordinates[0] * longitude_scale, coordinates[1]];
+        let target = to_plane(coordinates);
+
+        let first_segment_i = (min_position.floor() as usize).min(self.points.len() - 2);
+        let mut best: Option<(f64, f64)> = None;
+        for segment_i in first_segment_i..self.points.len() - 1 {
+            let from = to_plane(self.point_coordinates(segment_i));
+            let to = to_plane(self.point_coordinates(segment_i + 1));
+            let direction = [to[0] - from[0], to[1] - from[1]];
+            let length_squared = direction[0] * direction[0] + direction[1] * direction[1];
+            let mut fraction = if length_squared > 0.0 {
+                ((target[0] - from[0]) * direction[0] + (target[1] - from[1]) * direction[1])
+                    / length_squared
+            } else {
+                0.0
+            };
+            fraction = fraction.clamp(0.0, 1.0);
+            let position = (segment_i as f64 + fraction).max(min_position);
+            let fraction = position - segment_i as f64;
+            let closest = [
+                from[0] + direction[0] * fraction,
+                from[1] + direction[1] * fraction,
+            ];
+            let distance_squared =
+                (closest[0] - target[0]).powi(2) + (closest[1] - target[1]).powi(2);
+            if best
+                .is_none_or(|(best_distance_squared, _)| distance_squared < best_distance_squared)
+            {
+                best = Some((distance_squared, position));
+            }
+        }
+        best.map(|(_, position)| position)
+    }
+}
+
+pub struct LegGeometry {
+    /// `[longitude, latitude]` pairs, empty if a stop has no coordinates.
+    pub coordinates: Vec<[f64; 2]>,
+    /// Whether the coordinates follow the shape or are a straight line between the stops.
+    pub follows_shape: bool,
+}
+
+/// Lines between consecutive stops of a trip, following the shape where the stops could be
+/// located on it and straight lines otherwise.
+pub fn trip_leg_geometries(
+    shape: Option<&ArchivedGtfsShape>,
+    stops: &[ShapeStop],
+) -> Vec<LegGeometry> {
+    let positions = match shape {
+        Some(shape) => shape.locate_stops(stops),
+        None => vec![None; stops.len()],
+    };
+    (0..stops.len().saturating_sub(1))
+        .map(
+            |leg_i| match (shape, positions[leg_i], positions[leg_i + 1]) {
+                // Stops at the same position mean that the shape does not match the trip.
+                (Some(shape), Some(from_position), Some(to_position))
+                    if to_position > from_position =>
+                {
+                    LegGeometry {
+                        coordinates: shape.slice(from_position, to_position),
+                        follows_shape: true,
+                    }
+                }
+                _ => LegGeometry {
+                    coordinates: match (stops[leg_i].coordinates, stops[leg_i + 1].coordinates) {
+                        (Some(from), Some(to)) => vec![from, to],
+                        _ => vec![],
+                    },
+                    follows_shape: false,
+                },
+            },
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `[longitude, latitude, dist_traveled]` per point.
+    fn shape_bytes(points: &[(f64, f64, Option<f32>)]) -> rkyv::util::AlignedVec {
+        let shape = GtfsShape {
+            id: "SH".to_string(),
+            points: points
+                .iter()
+                .map(|(longitude, latitude, dist_traveled)| GtfsShapePoint {
+                    latitude: *latitude,
+                    longitude: *longitude,
+                    dist_traveled: *dist_traveled,
+                })
+                .collect(),
+        };
+        rkyv::to_bytes::<rkyv::rancor::Error>(&shape).unwrap()
+    }
+
+    fn access(bytes: &[u8]) -> &ArchivedGtfsShape {
+        rkyv::access::<ArchivedGtfsShape, rkyv::rancor::Error>(bytes).unwrap()
+    }
+
+    /// A square loop that starts and ends at the origin and then continues west, so the origin
+    /// is passed twice.
+    fn loop_points() -> Vec<(f64, f64, Option<f32>)> {
+        [
+            (0.0, 0.0),
+            (0.01, 0.0),
+            (0.01, 0.01),
+            (0.0, 0.01),
+            (0.0, 0.0),
+            (-0.01, 0.0),
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, (longitude, latitude))| (*longitude, *latitude, Some(i as f32 * 100.0)))
+        .collect()
+    }
+
+    fn at(coordinates: [f64; 2]) -> ShapeStop {
+        ShapeStop {
+            coordinates: Some(coordinates),
+            dist_traveled: None,
+        }
+    }
+
+    fn assert_positions(positions: &[Option<f64>], expected: &[Option<f64>]) {
+        assert_eq!(positions.len(), expected.len());
+        for (position, expected) in positions.iter().zip(expected) {
+            match (position, expected) {
+                (Some(position), Some(expected)) => {
+                    assert!((position - expected).abs() < 1e-6, "{:?}", positions)
+                }
+                _ => assert_eq!(position, expected),
+            }
+        }
+    }
+
+    #[test]
+    fn locates_stops_by_distance() {
+        let bytes = shape_bytes(&loop_points());
+        let shape = access(&bytes);
+        let by_distance = |dist_traveled| ShapeStop {
+            // Far away, so that projecting would give a different position.
+            coordinates: Some([1.0, 1.0]),
+            dist_traveled: Some(dist_traveled),
+        };
+        let positions = shape.locate_stops(&[
+            by_distance(-10.0),
+            by_distance(250.0),
+            by_distance(400.0),
+            by_distance(900.0),
+        ]);
+        assert_positions(&positions, &[Some(0.0), Some(2.5), Some(4.0), Some(5.0)]);
+    }
+
+    #[test]
+    fn locates_stops_on_a_shape_that_passes_a_place_twice() {
+        let points: Vec<_> = loop_points()
+            .into_iter()
+            .map(|(longitude, latitude, _)| (longitude, latitude, None))
+            .collect();
+        let bytes = shape_bytes(&points);
+        let shape = access(&bytes);
+        let positions = shape.locate_stops(&[
+            at([0.0, 0.0]),
+            at([0.01, 0.005]),
+            at([0.0, 0.0]),
+            at([-0.01, 0.0]),
+            ShapeStop {
+                coordinates: None,
+                dist_traveled: None,
+            },
+        ]);
+        assert_positions(
+            &positions,
+            &[Some(0.0), Some(1.5), Some(4.0), Some(5.0), None],
+        );
+    }
+
+    #[test]
+    fn projects_without_distances_on_any_point() {
+        let mut points = loop_points();
+        points[3].2 = None;
+        let bytes = shape_bytes(&points);
+        let shape = access(&bytes);
+        let positions = shape.locate_stops(&[ShapeStop {
+            coordinates: Some([0.005, 0.01]),
+            dist_traveled: Some(0.0),
+        }]);
+        assert_positions(&positions, &[Some(2.5)]);
+    }
+
+    #[test]
+    fn projects_after_the_minimum_position() {
+        let bytes = shape_bytes(&loop_points());
+        let shape = access(&bytes);
+        assert_positions(
+            &[
+                shape.project([0.005, -0.001], 0.0),
+                shape.project([0.0, 0.0], 0.0),
+                shape.project([0.0, 0.0], 3.0),
+                // The corner at position 1 is closest, but before the minimum position.
+                shape.project([0.01, 0.0], 3.5),
+            ],
+            &[Some(0.5), Some(0.0), Some(4.0), Some(4.0)],
+        );
+        let bytes = shape_bytes(&[]);
+        assert_eq!(access(&bytes).project([0.0, 0.0], 0.0), None);
+    }
+
+    #[test]
+    fn slices_between_positions() {
+        let bytes = shape_bytes(&loop_points());
+        let shape = access(&bytes);
+        let expected = vec![[0.005, 0.0], [0.01, 0.0], [0.01, 0.01], [0.005, 0.01]];
+        assert_eq!(shape.slice(0.5, 2.5), expected);
+        assert_eq!(shape.slice(2.5, 0.5), expected);
+        assert_eq!(shape.slice(1.0, 2.0), vec![[0.01, 0.0], [0.01, 0.01]]);
+        assert_eq!(shape.slice(4.0, 7.0), vec![[0.0, 0.0], [-0.01, 0.0]]);
+    }
+}
@@ -0,0 +1,224 @@
+use anyhow::Result;
+use std::{io::Write, path::Path};
+
+use crate::{
+    compression::{self, Compression},
+    geojson,
+};
+
+/// Identifies files in the binary station format.
+const BINARY_MAGIC: &[u8; 4] = b"TAST";
+const BINARY_VERSION: u32 = 1;
+const BINARY_FLAG_HAS_NAMES: u32 = 1;
+/// Stored in the binary format for stations without time.
+pub const BINARY_NO_TIME: u32 = u32::MAX;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum StationOutputFormat {
+    /// Pretty printed `{"stations": [...]}` as used by the frontend.
+    Json,
+    /// FeatureCollection with one point per station.
+    Geojson,
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+    /// Packed arrays that can be uploaded to WebGL buffers directly, see `write_binary`.
+    Binary,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct StationOutputArgs {
+    #[arg(long)]
+    pub output_path: String,
+    #[arg(long, value_enum, default_value_t = StationOutputFormat::Json)]
+    pub format: StationOutputFormat,
+    /// Compression applied to the entire output file. Not available for the binary format.
+    #[arg(long, value_enum, default_value_t = Compression::None)]
+    pub compression: Compression,
+    /// Leave out the string table with station names in the binary format.
+    #[arg(long)]
+    pub without_names: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct OutputStation {
+    pub id: String,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Time in seconds, its meaning depends on the exporter.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<u32>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct OutputStations<'a> {
+    stations: &'a [OutputStation],
+}
+
+/// Unlike `OutputStation`, all columns are always written so that every row has the same
+/// number of fields.
+#[derive(Debug, Clone, serde::Serialize)]
+struct StationCsvRecord<'a> {
+    id: &'a str,
+    name: &'a str,
+    latitude: f64,
+    longitude: f64,
+    time: Option<u32>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct StationProperties<'a> {
+    id: &'a str,
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<u32>,
+}
+
+impl StationOutputFormat {
+    pub fn file_extension(self) -> &'static str {
+        match self {
+            StationOutputFormat::Json => "json",
+            StationOutputFormat::Geojson => "geojson",
+            StationOutputFormat::Csv => "csv",
+            StationOutputFormat::Ndjson => "ndjson",
+            StationOutputFormat::Binary => "bin",
+        }
+    }
+}
+
+pub fn write_stations(stations: &[OutputStation], output: &StationOutputArgs) -> Result<()> {
+    // The frontend parses binary files as they are fetched. Compression can still be applied by
+    // the server with `Content-Encoding`, which the browser removes transparently.
+    if matches!(output.format, StationOutputFormat::Binary)
+        && !matches!(output.compression, Compression::None)
+    {
+        anyhow::bail!(
+            "Binary station files can not be compressed, since the frontend reads them directly"
+        );
+    }
+    let buffer = encode_stations(stations, output.format, !output.without_names)?;
+    compression::write_compressed_file(Path::new(&output.output_path), &buffer, output.compression)
+}
+
+pub fn encode_stations(
+    stations: &[OutputStation],
+    format: StationOutputFormat,
+    with_names: bool,
+) -> Result<Vec<u8>> {
+    let mut buffer = vec![];
+    match format {
+        StationOutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut buffer, &OutputStations { stations })?;
+        }
+        StationOutputFormat::Geojson => {
+            let feature_collection = geojson::FeatureCollection {
+                features: stations
+                    .iter()
+                    .map(|station| geojson::Feature {
+                        geometry: geojson::Geometry::Point([station.longitude, station.latitude]),
+                        properties: StationProperties {
+                            id: &station.id,
+                            name: &station.name,
+                            time: station.time,
+                        },
+                    })
+                    .collect(),
+            };
+            serde_json::to_writer(&mut buffer, &feature_collection)?;
+        }
+        StationOutputFormat::Csv => {
+            // The header is written up front so that it is there even without stations.
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(&mut buffer);
+            writer.write_record(["id", "name", "latitude", "longitude", "time"])?;
+            for station in stations {
+                writer.serialize(StationCsvRecord {
+                    id: &station.id,
+                    name: &station.name,
+                    latitude: station.latitude,
+                    longitude: station.longitude,
+                    time: station.time,
+                })?;
+            }
+            writer.flush()?;
+        }
+        StationOutputFormat::Ndjson => {
+            for station in stations {
+                serde_json::to_writer(&mut buffer, station)?;
+                buffer.push(b'\n');
+            }
+        }
+        StationOutputFormat::Binary => {
+            write_binary(stations, with_names, &mut buffer)?;
+        }
+    }
+    Ok(buffer)
+}
+
+/// Writes stations in a compact little-endian binary layout:
+/// - Header: magic `TAST`, version (u32), stations num (u32), flags (u32).
+/// - Positions: longitude and latitude (f32) for each station.
+/// - Times: one u32 per station, `BINARY_NO_TIME` if there is none.
+/// - Names (if the flag is set): `stations_num + 1` u32 byte offsets followed by the UTF-8
+///   data of all names.
+///
+/// All sections start at multiples of four bytes so that they can be viewed as typed arrays.
+pub fn write_binary(
+    stations: &[OutputStation],
+    with_names: bool,
+    writer: &mut impl Write,
+) -> Result<()> {
+    writer.write_all(BINARY_MAGIC)?;
+    writer.write_all(&BINARY_VERSION.to_le_bytes())?;
+    writer.write_all(&(stations.len() as u32).to_le_bytes())?;
+    let flags = if with_names { BINARY_FLAG_HAS_NAMES } else { 0 };
+    writer.write_all(&flags.to_le_bytes())?;
+    for station in stations {
+        writer.write_all(&(station.longitude as f32).to_le_bytes())?;
+        writer.write_all(&(station.latitude as f32).to_le_bytes())?;
+    }
+    for station in stations {
+        writer.write_all(&station.time.unwrap_or(BINARY_NO_TIME).to_le_bytes())?;
+    }
+    if with_names {
+        let mut offset = 0u32;
+        writer.write_all(&offset.to_le_bytes())?;
+        for station in stations {
+            offset += station.name.len() as u32;
+            writer.write_all(&offset.to_le_bytes())?;
+        }
+        for station in stations {
+            writer.write_all(station.name.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_without_stations_has_header() {
+        let buffer = encode_stations(&[], StationOutputFormat::Csv, true).unwrap();
+        assert_eq!(buffer, b"id,name,latitude,longitude,time\n");
+    }
+
+    #[test]
+    fn csv_rows_match_header() {
+        let stations = [OutputStation {
+            id: "S1".to_string(),
+            name: "Hennigsdorf".to_string(),
+            latitude: 52.5,
+            longitude: 13.25,
+            time: None,
+        }];
+        let buffer = encode_stations(&stations, StationOutputFormat::Csv, true).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "id,name,latitude,longitude,time\nS1,Hennigsdorf,52.5,13.25,\n"
+        );
+    }
+}
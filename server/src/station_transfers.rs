@@ -0,0 +1,413 @@
+use geo::Distance;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use crate::{
+    gtfs_rkyv::{self, ArchivedGtfsPathwayMode},
+    walking,
+};
+
+/// Transfer time between two platforms of a station that are not connected by pathways.
+pub const NO_TRANSFER: u32 = u32::MAX;
+
+/// Time to change one level with stairs, escalators or elevators if the feed doesn't give a
+/// traversal time.
+const SECONDS_PER_LEVEL: f64 = 20.0;
+
+/// Walking times between the boarding locations of a station, derived from `pathways.txt`.
+pub struct StationTransfers {
+    /// Sorted indices into `GtfsData.stops` of the boarding locations that are connected to
+    /// pathways.
+    pub stop_indices: Vec<u32>,
+    /// Fastest walking time between every pair of `stop_indices` in row-major order, with the
+    /// origin as row. `NO_TRANSFER` if there is no path.
+    pub times: Vec<u32>,
+    /// Same as `times`, but only using pathways without stairs or escalators.
+    pub step_free_times: Vec<u32>,
+}
+
+impl StationTransfers {
+    /// Swaps origins and destinations, as used for searches on the reversed connections.
+    pub fn transposed(&self) -> StationTransfers {
+        let stops_num = self.stop_indices.len();
+        let transpose = |times: &[u32]| {
+            let mut transposed = vec![NO_TRANSFER; times.len()];
+            for from_i in 0..stops_num {
+                for to_i in 0..stops_num {
+                    transposed[to_i * stops_num + from_i] = times[from_i * stops_num + to_i];
+                }
+            }
+            transposed
+        };
+        StationTransfers {
+            stop_indices: self.stop_indices.clone(),
+            times: transpose(&self.times),
+            step_free_times: transpose(&self.step_free_times),
+        }
+    }
+}
+
+struct PathwayEdge {
+    to_stop_i: usize,
+    seconds: u32,
+    is_step_free: bool,
+}
+
+/// Computes the walking times between the boarding locations of all stations that have
+/// pathways. `root_stop_indices` maps every stop to the stop at the top of its parent chain,
+/// and the result is keyed by that root stop. Pathways between different stations are ignored.
+pub fn compute_station_transfers(
+    gtfs_rkyv: &gtfs_rkyv::ArchivedGtfsData,
+    stop_index_by_id: &HashMap<&str, usize>,
+    root_stop_indices: &[Option<usize>],
+) -> HashMap<usize, StationTransfers> {
+    let level_index_by_id: HashMap<&str, f64> = gtfs_rkyv
+        .levels
+        .iter()
+        .map(|level| (level.id.as_str(), level.index.to_native()))
+        .collect();
+
+    let mut edges_by_stop_i: HashMap<usize, Vec<PathwayEdge>> = HashMap::new();
+    // Also contains stops that are only reached by one-way pathways and have no edges.
+    let mut pathway_stop_indices = HashSet::new();
+    for pathway in gtfs_rkyv.pathways.iter() {
+        let (Some(from_stop_i), Some(to_stop_i)) = (
+            stop_index_by_id.get(pathway.from_stop_id.as_str()),
+            stop_index_by_id.get(pathway.to_stop_id.as_str()),
+        ) else {
+            continue;
+        };
+        if root_stop_indices[*from_stop_i].is_none()
+            || root_stop_indices[*from_stop_i] != root_stop_indices[*to_stop_i]
+        {
+            continue;
+        }
+        let seconds = pathway_seconds(
+            pathway,
+            &gtfs_rkyv.stops[*from_stop_i],
+            &gtfs_rkyv.stops[*to_stop_i],
+            &level_index_by_id,
+        );
+        let is_step_free = pathway.mode.is_step_free()
+            && pathway
+                .stair_count
+                .as_ref()
+                .is_none_or(|stair_count| stair_count.to_native() == 0);
+        let mut add_edge = |from_stop_i: usize, to_stop_i: usize| {
+            edges_by_stop_i
+                .entry(from_stop_i)
+                .or_default()
+                .push(PathwayEdge {
+                    to_stop_i,
+                    seconds,
+                    is_step_free,
+                });
+        };
+        add_edge(*from_stop_i, *to_stop_i);
+        if pathway.is_bidirectional {
+            add_edge(*to_stop_i, *from_stop_i);
+        }
+        pathway_stop_indices.extend([*from_stop_i, *to_stop_i]);
+    }
+
+    let mut boarding_stop_indices_by_root: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (stop_i, stop) in gtfs_rkyv.stops.iter().enumerate() {
+        let Some(root_stop_i) = root_stop_indices[stop_i] else {
+            continue;
+        };
+        if stop.location_type.is_boarding_location() && pathway_stop_indices.contains(&stop_i) {
+            boarding_stop_indices_by_root
+                .entry(root_stop_i)
+                .or_default()
+                .push(stop_i);
+        }
+    }
+
+    let mut transfers_by_root = HashMap::new();
+    for (root_stop_i, boarding_stop_indices) in boarding_stop_indices_by_root {
+        let mut times = vec![];
+        let mut step_free_times = vec![];
+        for from_stop_i in &boarding_stop_indices {
+            for (step_free, times) in [(false, &mut times), (true, &mut step_free_times)] {
+                let seconds_by_stop_i = shortest_walks(&edges_by_stop_i, *from_stop_i, step_free);
+                times.extend(boarding_stop_indices.iter().map(|to_stop_i| {
+                    seconds_by_stop_i
+                        .get(to_stop_i)
+                        .copied()
+                        .unwrap_or(NO_TRANSFER)
+                }));
+            }
+        }
+        transfers_by_root.insert(
+            root_stop_i,
+            StationTransfers {
+                stop_indices: boarding_stop_indices.iter().map(|i| *i as u32).collect(),
+                times,
+                step_free_times,
+            },
+        );
+    }
+    transfers_by_root
+}
+
+/// Estimates the time to walk through a pathway if the feed doesn't give it.
+fn pathway_seconds(
+    pathway: &gtfs_rkyv::ArchivedGtfsPathway,
+    from_stop: &gtfs_rkyv::ArchivedGtfsStop,
+    to_stop: &gtfs_rkyv::ArchivedGtfsStop,
+    level_index_by_id: &HashMap<&str, f64>,
+) -> u32 {
+    if let Some(traversal_time) = pathway.traversal_time.as_ref() {
+        return traversal_time.to_native();
+    }
+    let length = match pathway.length.as_ref() {
+        Some(length) => length.to_native() as f64,
+        None => match (
+            from_stop.latitude.as_ref(),
+            from_stop.longitude.as_ref(),
+            to_stop.latitude.as_ref(),
+            to_stop.longitude.as_ref(),
+        ) {
+            (Some(from_latitude), Some(from_longitude), Some(to_latitude), Some(to_longitude)) => {
+                geo::Haversine.distance(
+                    geo::Point::new(from_longitude.to_native(), from_latitude.to_native()),
+                    geo::Point::new(to_longitude.to_native(), to_latitude.to_native()),
+                )
+            }
+            _ => 0.0,
+        },
+    };
+    let level_index = |stop: &gtfs_rkyv::ArchivedGtfsStop| {
+        stop.level_id
+            .as_ref()
+            .and_then(|level_id| level_index_by_id.get(level_id.as_str()))
+            .copied()
+    };
+    let levels_num = match (&pathway.mode, level_index(from_stop), level_index(to_stop)) {
+        (
+            ArchivedGtfsPathwayMode::Stairs
+            | ArchivedGtfsPathwayMode::Escalator
+            | ArchivedGtfsPathwayMode::Elevator,
+            Some(from_level_index),
+            Some(to_level_index),
+        ) => (to_level_index - from_level_index).abs(),
+        _ => 0.0,
+    };
+    (length / walking::DEFAULT_WALKING_SPEED + levels_num * SECONDS_PER_LEVEL).round() as u32
+}
+
+/// Dijkstra over the pathways from one stop, returning the time to every reachable stop.
+fn shortest_walks(
+    edges_by_stop_i: &HashMap<usize, Vec<PathwayEdge>>,
+    from_stop_i: usize,
+    step_free: bool,
+) -> HashMap<usize, u32> {
+    let mut seconds_by_stop_i = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    seconds_by_stop_i.insert(from_stop_i, 0);
+    queue.push(Reverse((0, from_stop_i)));
+    while let Some(Reverse((seconds, stop_i))) = queue.pop() {
+        if seconds_by_stop_i
+            .get(&stop_i)
+            .is_some_and(|best| *best < seconds)
+        {
+            continue;
+        }
+        for edge in edges_by_stop_i.get(&stop_i).into_iter().flatten() {
+            if step_free && !edge.is_step_free {
+                continue;
+            }
+            let next_seconds = seconds + edge.seconds;
+            if seconds_by_stop_i
+                .get(&edge.to_stop_i)
+                .is_none_or(|best| next_seconds < *best)
+            {
+                seconds_by_stop_i.insert(edge.to_stop_i, next_seconds);
+                queue.push(Reverse((next_seconds, edge.to_stop_i)));
+            }
+        }
+    }
+    seconds_by_stop_i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gtfs_rkyv::{
+            GtfsAvailability, GtfsData, GtfsLevel, GtfsLocationType, GtfsPathway, GtfsPathwayMode,
+            GtfsStop,
+        },
+        prepare_direct_connections_rkyv::ConnectionsFromStation,
+        stop_times,
+    };
+
+    fn stop(
+        id: &str,
+        location_type: GtfsLocationType,
+        parent_station_id: Option<&str>,
+        level_id: Option<&str>,
+    ) -> GtfsStop {
+        GtfsStop {
+            id: id.to_string(),
+            code: None,
+            name: None,
+            parent_station_id: parent_station_id.map(|id| id.to_string()),
+            latitude: None,
+            longitude: None,
+            location_type,
+            level_id: level_id.map(|id| id.to_string()),
+            wheelchair_boarding: GtfsAvailability::InformationNotAvailable,
+            timezone: None,
+        }
+    }
+
+    fn pathway(
+        from_stop_id: &str,
+        to_stop_id: &str,
+        mode: GtfsPathwayMode,
+        is_bidirectional: bool,
+        traversal_time: Option<u32>,
+    ) -> GtfsPathway {
+        GtfsPathway {
+            from_stop_id: from_stop_id.to_string(),
+            to_stop_id: to_stop_id.to_string(),
+            mode,
+            is_bidirectional,
+            length: None,
+            traversal_time,
+            stair_count: None,
+        }
+    }
+
+    /// Station S with platforms P1 on the ground level and P2 and P3 one level up. P1 and P2
+    /// are connected by stairs and, through the node N, by an elevator without a traversal
+    /// time. P3 can only be reached from P2 by stairs in one direction. P4 has no pathways.
+    fn station() -> GtfsData {
+        let platform =
+            |id, level_id| stop(id, GtfsLocationType::StopPoint, Some("S"), Some(level_id));
+        let stops = vec![
+            stop("S", GtfsLocationType::Station, None, None),
+            platform("P1", "L0"),
+            platform("P2", "L1"),
+            stop("N", GtfsLocationType::GenericNode, Some("S"), Some("L0")),
+            platform("P3", "L1"),
+            platform("P4", "L0"),
+            stop("T", GtfsLocationType::StopPoint, None, None),
+        ];
+        let pathways = vec![
+            pathway("P1", "P2", GtfsPathwayMode::Stairs, true, Some(40)),
+            pathway("P1", "N", GtfsPathwayMode::Walkway, true, Some(30)),
+            pathway("N", "P2", GtfsPathwayMode::Elevator, true, None),
+            pathway("P2", "P3", GtfsPathwayMode::Stairs, false, Some(45)),
+            // Leads to another station and is ignored.
+            pathway("P1", "T", GtfsPathwayMode::Walkway, true, Some(10)),
+        ];
+        let level = |id: &str, index| GtfsLevel {
+            id: id.to_string(),
+            index,
+            name: None,
+        };
+        GtfsData {
+            agencies: vec![],
+            services: vec![],
+            pathways,
+            levels: vec![level("L0", 0.0), level("L1", 1.0)],
+            routes: vec![],
+            shapes: vec![],
+            stops,
+            stop_times: stop_times::build_stop_times(vec![], 0),
+            trips: vec![],
+        }
+    }
+
+    fn station_transfers() -> StationTransfers {
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&station()).unwrap();
+        let gtfs_rkyv =
+            rkyv::access::<gtfs_rkyv::ArchivedGtfsData, rkyv::rancor::Error>(&bytes).unwrap();
+        let stop_index_by_id: HashMap<&str, usize> = gtfs_rkyv
+            .stops
+            .iter()
+            .enumerate()
+            .map(|(stop_i, stop)| (stop.id.as_str(), stop_i))
+            .collect();
+        let root_stop_indices = [0, 0, 0, 0, 0, 0, 6].map(Some);
+        let mut transfers_by_root =
+            compute_station_transfers(gtfs_rkyv, &stop_index_by_id, &root_stop_indices);
+        assert_eq!(transfers_by_root.len(), 1);
+        transfers_by_root.remove(&0).unwrap()
+    }
+
+    #[test]
+    fn computes_walking_times_between_platforms() {
+        let transfers = station_transfers();
+        assert_eq!(transfers.stop_indices, vec![1, 2, 4]);
+        #[rustfmt::skip]
+        assert_eq!(
+            transfers.times,
+            vec![
+                0, 40, 85,
+                40, 0, 45,
+                NO_TRANSFER, NO_TRANSFER, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn step_free_times_avoid_stairs() {
+        let transfers = station_transfers();
+        // The elevator takes 20 seconds for one level.
+        #[rustfmt::skip]
+        assert_eq!(
+            transfers.step_free_times,
+            vec![
+                0, 50, NO_TRANSFER,
+                50, 0, NO_TRANSFER,
+                NO_TRANSFER, NO_TRANSFER, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn transposes_one_way_pathways() {
+        let transfers = station_transfers().transposed();
+        #[rustfmt::skip]
+        assert_eq!(
+            transfers.times,
+            vec![
+                0, 40, NO_TRANSFER,
+                40, 0, NO_TRANSFER,
+                85, 45, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn transfer_time_between_platforms() {
+        let transfers = station_transfers();
+        let connections_from_station = ConnectionsFromStation {
+            main_stop_i: 0,
+            connections: vec![],
+            transfer_stop_indices: transfers.stop_indices,
+            transfer_times: transfers.times,
+            step_free_transfer_times: transfers.step_free_times,
+        };
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&connections_from_station).unwrap();
+        let station =
+            rkyv::access::<rkyv::Archived<ConnectionsFromStation>, rkyv::rancor::Error>(&bytes)
+                .unwrap();
+
+        assert_eq!(station.transfer_time(Some(1), 2, false), Some(40));
+        assert_eq!(station.transfer_time(Some(1), 2, true), Some(50));
+        assert_eq!(station.transfer_time(Some(1), 4, false), Some(85));
+        assert_eq!(station.transfer_time(Some(1), 4, true), None);
+        // Without a path, platforms are treated like stations without pathways.
+        assert_eq!(station.transfer_time(Some(4), 1, false), Some(0));
+        assert_eq!(station.transfer_time(Some(4), 1, true), None);
+        // The platform without pathways and the start of a search need no transfer.
+        assert_eq!(station.transfer_time(Some(1), 5, true), Some(0));
+        assert_eq!(station.transfer_time(None, 4, true), Some(0));
+    }
+}
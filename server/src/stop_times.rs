@@ -0,0 +1,120 @@
+use std::ops::Range;
+
+use crate::gtfs_rkyv::{ArchivedGtfsStopTimes, GtfsStopTime, GtfsStopTimes};
+
+/// Sorts the stop times by trip and stop sequence and splits them into columns.
+pub fn build_stop_times(mut stop_times: Vec<GtfsStopTime>, trips_num: usize) -> GtfsStopTimes {
+    stop_times.sort_by_key(|stop_time| (stop_time.trip_i, stop_time.stop_sequence));
+
+    let mut trip_offsets = Vec::with_capacity(trips_num + 1);
+    let mut stop_time_i = 0;
+    for trip_i in 0..trips_num {
+        trip_offsets.push(stop_time_i as u32);
+        while stop_time_i < stop_times.len() && stop_times[stop_time_i].trip_i as usize == trip_i {
+            stop_time_i += 1;
+        }
+    }
+    trip_offsets.push(stop_time_i as u32);
+
+    GtfsStopTimes {
+        trip_offsets,
+        arrival_times: stop_times
+            .iter()
+            .map(|stop_time| stop_time.arrival_time)
+            .collect(),
+        departure_times: stop_times
+            .iter()
+            .map(|stop_time| stop_time.departure_time)
+            .collect(),
+        stop_indices: stop_times
+            .iter()
+            .map(|stop_time| stop_time.stop_i)
+            .collect(),
+        shape_dist_traveled: stop_times
+            .iter()
+            .map(|stop_time| stop_time.shape_dist_traveled)
+            .collect(),
+        interpolated: stop_times
+            .iter()
+            .map(|stop_time| stop_time.interpolated)
+            .collect(),
+    }
+}
+
+impl ArchivedGtfsStopTimes {
+    /// Indices of the stop times of the trip, in the order of the stop sequence.
+    pub fn trip_range(&self, trip_i: usize) -> Range<usize> {
+        self.trip_offsets[trip_i].to_native() as usize
+            ..self.trip_offsets[trip_i + 1].to_native() as usize
+    }
+
+    pub fn stop_i(&self, stop_time_i: usize) -> usize {
+        self.stop_indices[stop_time_i].to_native() as usize
+    }
+
+    pub fn arrival_time(&self, stop_time_i: usize) -> Option<u32> {
+        self.arrival_times[stop_time_i]
+            .as_ref()
+            .map(|time| time.to_native())
+    }
+
+    pub fn departure_time(&self, stop_time_i: usize) -> Option<u32> {
+        self.departure_times[stop_time_i]
+            .as_ref()
+            .map(|time| time.to_native())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop_time(trip_i: u32, stop_sequence: u16, stop_i: u32, time: u32) -> GtfsStopTime {
+        GtfsStopTime {
+            arrival_time: Some(time),
+            departure_time: Some(time + 60),
+            stop_i,
+            stop_sequence,
+            trip_i,
+            shape_dist_traveled: None,
+            interpolated: false,
+        }
+    }
+
+    #[test]
+    fn sorts_by_trip_and_stop_sequence() {
+        let stop_times = build_stop_times(
+            vec![
+                stop_time(2, 1, 7, 900),
+                stop_time(0, 20, 5, 300),
+                stop_time(0, 3, 4, 0),
+                stop_time(0, 10, 6, 120),
+            ],
+            3,
+        );
+        assert_eq!(stop_times.trip_offsets, vec![0, 3, 3, 4]);
+        assert_eq!(stop_times.stop_indices, vec![4, 6, 5, 7]);
+        assert_eq!(
+            stop_times.arrival_times,
+            vec![Some(0), Some(120), Some(300), Some(900)]
+        );
+        assert_eq!(
+            stop_times.departure_times,
+            vec![Some(60), Some(180), Some(360), Some(960)]
+        );
+    }
+
+    #[test]
+    fn trips_without_stop_times_have_empty_ranges() {
+        let stop_times = build_stop_times(vec![stop_time(1, 1, 0, 0), stop_time(1, 2, 1, 60)], 4);
+        assert_eq!(stop_times.trip_offsets, vec![0, 0, 2, 2, 2]);
+
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&stop_times).unwrap();
+        let archived = rkyv::access::<ArchivedGtfsStopTimes, rkyv::rancor::Error>(&bytes).unwrap();
+        assert!(archived.trip_range(0).is_empty());
+        assert_eq!(archived.trip_range(1), 0..2);
+        assert_eq!(archived.stop_i(1), 1);
+        assert_eq!(archived.departure_time(1), Some(120));
+        assert!(archived.trip_range(3).is_empty());
+    }
+}
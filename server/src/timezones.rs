@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::gtfs_rkyv::{ArchivedGtfsData, ArchivedGtfsStop};
+
+pub fn parse_timezone(name: &str) -> Result<Tz> {
+    name.parse()
+        .map_err(|_| anyhow::anyhow!("Unknown timezone {:?}", name))
+}
+
+impl ArchivedGtfsData {
+    /// Timezone that the stop times are given in. GTFS requires all agencies to use the same
+    /// timezone, so the first one is taken.
+    pub fn feed_timezone(&self) -> Result<Tz> {
+        let agency = self
+            .agencies
+            .first()
+            .context("The feed has no agency to take the timezone from")?;
+        parse_timezone(agency.timezone.as_str())
+    }
+}
+
+/// Instant from which the stop times of a service day are counted. GTFS defines it as noon
+/// minus 12 hours, which is midnight except on days with a daylight saving time change, where
+/// it is one hour off.
+pub fn service_day_start(date: NaiveDate, timezone: Tz) -> DateTime<Utc> {
+    let noon = date.and_time(NaiveTime::from_hms_opt(12, 0, 0).unwrap());
+    // Noon is never skipped or repeated by a transition, but fall back to treating it as UTC
+    // rather than failing for exotic timezones.
+    let noon = timezone
+        .from_local_datetime(&noon)
+        .earliest()
+        .map(|noon| noon.with_timezone(&Utc))
+        .unwrap_or_else(|| noon.and_utc());
+    noon - Duration::hours(12)
+}
+
+/// Converts a stop time in seconds of the given service day to an absolute instant. Times
+/// after 24:00:00 end up on the following day.
+pub fn stop_time_to_utc(date: NaiveDate, timezone: Tz, seconds: u32) -> DateTime<Utc> {
+    service_day_start(date, timezone) + Duration::seconds(seconds as i64)
+}
+
+/// Timezone for showing times at the stop, which is the stop's own timezone if it has a valid
+/// one and the feed timezone otherwise.
+pub fn stop_timezone(stop: &ArchivedGtfsStop, feed_timezone: Tz) -> Tz {
+    stop.timezone
+        .as_ref()
+        .and_then(|timezone| parse_timezone(timezone.as_str()).ok())
+        .unwrap_or(feed_timezone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn utc(date: NaiveDate, hour: u32) -> DateTime<Utc> {
+        date.and_hms_opt(hour, 0, 0).unwrap().and_utc()
+    }
+
+    fn local_midnight(date: NaiveDate, timezone: Tz) -> DateTime<Utc> {
+        timezone
+            .from_local_datetime(&date.and_time(NaiveTime::MIN))
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn regular_day_starts_at_midnight() {
+        let timezone = chrono_tz::Europe::Berlin;
+        assert_eq!(
+            service_day_start(date(2024, 3, 30), timezone),
+            local_midnight(date(2024, 3, 30), timezone)
+        );
+        assert_eq!(
+            service_day_start(date(2024, 7, 1), timezone),
+            utc(date(2024, 6, 30), 22)
+        );
+    }
+
+    #[test]
+    fn spring_forward_day_starts_an_hour_before_midnight() {
+        let timezone = chrono_tz::Europe::Berlin;
+        let day = date(2024, 3, 31);
+        let start = service_day_start(day, timezone);
+        // Noon is 10:00 UTC in summer time, so the day starts at 23:00 CET on the day before.
+        assert_eq!(start, utc(date(2024, 3, 30), 22));
+        assert_eq!(local_midnight(day, timezone) - start, Duration::hours(1));
+        // The local day only has 23 hours, so 24:00:00 is already the next midnight.
+        let next_midnight = local_midnight(date(2024, 4, 1), timezone);
+        assert_eq!(
+            next_midnight - local_midnight(day, timezone),
+            Duration::hours(23)
+        );
+        assert_eq!(stop_time_to_utc(day, timezone, 24 * 3600), next_midnight);
+    }
+
+    #[test]
+    fn fall_back_day_starts_an_hour_after_midnight() {
+        let timezone = chrono_tz::Europe::Berlin;
+        let day = date(2024, 10, 27);
+        let start = service_day_start(day, timezone);
+        // Noon is 11:00 UTC in winter time, so the day starts at 01:00 CEST.
+        assert_eq!(start, utc(date(2024, 10, 26), 23));
+        assert_eq!(start - local_midnight(day, timezone), Duration::hours(1));
+        // The local day has 25 hours, which the late start makes up for at 24:00:00.
+        let next_midnight = local_midnight(date(2024, 10, 28), timezone);
+        assert_eq!(
+            next_midnight - local_midnight(day, timezone),
+            Duration::hours(25)
+        );
+        assert_eq!(stop_time_to_utc(day, timezone, 24 * 3600), next_midnight);
+        assert_eq!(
+            stop_time_to_utc(day, timezone, 2 * 3600),
+            utc(date(2024, 10, 27), 1)
+        );
+    }
+}
@@ -0,0 +1,402 @@
+use anyhow::Result;
+use std::{io::Write, path::Path};
+
+use crate::{
+    connection_filter::{ConnectionFilter, ConnectionFilterArgs},
+    find_optimal_paths, gtfs_rkyv,
+    memory_mapped_rkyv::{self, MemoryMappedRkyv},
+    prepare_direct_connections_rkyv, prepare_gtfs_as_rkyv,
+};
+
+/// Travel time that is stored for origin-destination pairs without any path.
+pub const UNREACHABLE_TRAVEL_TIME: u32 = u32::MAX;
+
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
+#[rkyv(derive(Debug))]
+pub struct TravelTimeMatrix {
+    /// Indices into `AllConnections.stations`, one per row.
+    pub origin_station_indices: Vec<u32>,
+    /// Indices into `AllConnections.stations`, one per column.
+    pub destination_station_indices: Vec<u32>,
+    /// Travel times in seconds in row-major order.
+    pub travel_times: Vec<u32>,
+}
+
+impl ArchivedTravelTimeMatrix {
+    pub fn travel_time(&self, origin_i: usize, destination_i: usize) -> Option<u32> {
+        let destinations_num = self.destination_station_indices.len();
+        let travel_time =
+            self.travel_times[origin_i * destinations_num + destination_i].to_native();
+        (travel_time != UNREACHABLE_TRAVEL_TIME).then_some(travel_time)
+    }
+
+    /// Checks that the matrix fits the stations it is used with, since it is only valid for
+    /// the feed it was exported from.
+    fn validate(&self, stations_num: usize) -> Result<()> {
+        let expected_len =
+            self.origin_station_indices.len() * self.destination_station_indices.len();
+        if self.travel_times.len() != expected_len {
+            anyhow::bail!(
+                "The travel time matrix has {} travel times instead of {}",
+                self.travel_times.len(),
+                expected_len
+            );
+        }
+        if self
+            .origin_station_indices
+            .iter()
+            .chain(self.destination_station_indices.iter())
+            .any(|station_i| station_i.to_native() as usize >= stations_num)
+        {
+            anyhow::bail!("The travel time matrix was exported for a different GTFS feed");
+        }
+        Ok(())
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum TravelTimeMatrixFormat {
+    /// Memory mappable rkyv archive, see `load_travel_time_matrix`.
+    Binary,
+    /// One row per origin-destination pair.
+    Csv,
+    /// Directory with one raw little-endian file per column and a `schema.json`.
+    Columnar,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct ColumnarSchema {
+    rows_num: usize,
+    unreachable_seconds: u32,
+    columns: Vec<ColumnarColumn>,
+    stations: Vec<ColumnarStation>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct ColumnarColumn {
+    name: &'static str,
+    data_type: &'static str,
+    file_name: &'static str,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct ColumnarStation {
+    station_i: u32,
+    stop_id: String,
+    name: Option<String>,
+}
+
+// Safety: This is safe for as long as the underlying file is not modified.
+pub async unsafe fn load_travel_time_matrix(
+    path: &Path,
+) -> Result<MemoryMappedRkyv<'_, ArchivedTravelTimeMatrix>> {
+    unsafe {
+        memory_mapped_rkyv::load_memory_mapped_rkyv_checked::<ArchivedTravelTimeMatrix>(path).await
+    }
+}
+
+pub async fn export_travel_time_matrix(
+    gtfs_folder_path: &Path,
+    station_names: &[String],
+    output_path: &Path,
+    format: TravelTimeMatrixFormat,
+    connection_filter: &ConnectionFilterArgs,
+) -> Result<()> {
+    let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
+    let all_connections_rkyv =
+        prepare_direct_connections_rkyv::load_direct_connections_rkyv(gtfs_folder_path).await?;
+    let connection_filter = connection_filter.to_filter(&gtfs_rkyv)?;
+
+    let station_names: Vec<&str> = station_names.iter().map(|name| name.as_str()).collect();
+    let station_indices = find_optimal_paths::find_station_indices_by_names(
+        &gtfs_rkyv,
+        &all_connections_rkyv,
+        &station_names,
+    );
+    if station_indices.is_empty() {
+        anyhow::bail!("None of the given station names was found");
+    }
+
+    let matrix = compute_travel_time_matrix(
+        &all_connections_rkyv.stations,
+        &station_indices,
+        &station_indices,
+        &connection_filter,
+    );
+    let buffer = rkyv::to_bytes::<rkyv::rancor::Error>(&matrix)?;
+    let matrix_rkyv = rkyv::access::<ArchivedTravelTimeMatrix, rkyv::rancor::Error>(&buffer)?;
+    match format {
+        TravelTimeMatrixFormat::Binary => {
+            let mut file = std::fs::File::create(output_path)?;
+            file.write_all(&buffer)?;
+            Ok(())
+        }
+        TravelTimeMatrixFormat::Csv => write_travel_time_matrix_csv(
+            &gtfs_rkyv,
+            &all_connections_rkyv,
+            matrix_rkyv,
+            output_path,
+        ),
+        TravelTimeMatrixFormat::Columnar => write_travel_time_matrix_columnar(
+            &gtfs_rkyv,
+            &all_connections_rkyv,
+            matrix_rkyv,
+            output_path,
+        ),
+    }
+}
+
+/// Converts a previously exported binary matrix into one of the other formats.
+pub async fn convert_travel_time_matrix(
+    gtfs_folder_path: &Path,
+    matrix_path: &Path,
+    output_path: &Path,
+    format: TravelTimeMatrixFormat,
+) -> Result<()> {
+    let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
+    let all_connections_rkyv =
+        prepare_direct_connections_rkyv::load_direct_connections_rkyv(gtfs_folder_path).await?;
+    let matrix_rkyv = unsafe { load_travel_time_matrix(matrix_path).await? };
+    matrix_rkyv.validate(all_connections_rkyv.stations.len())?;
+    match format {
+        TravelTimeMatrixFormat::Binary => {
+            std::fs::copy(matrix_path, output_path)?;
+            Ok(())
+        }
+        TravelTimeMatrixFormat::Csv => write_travel_time_matrix_csv(
+            &gtfs_rkyv,
+            &all_connections_rkyv,
+            &matrix_rkyv,
+            output_path,
+        ),
+        TravelTimeMatrixFormat::Columnar => write_travel_time_matrix_columnar(
+            &gtfs_rkyv,
+            &all_connections_rkyv,
+            &matrix_rkyv,
+            output_path,
+        ),
+    }
+}
+
+fn write_travel_time_matrix_csv(
+    gtfs_rkyv: &gtfs_rkyv::ArchivedGtfsData,
+    all_connections_rkyv: &prepare_direct_connections_rkyv::ArchivedAllConnections,
+    matrix_rkyv: &ArchivedTravelTimeMatrix,
+    output_path: &Path,
+) -> Result<()> {
+    let station_stop = |station_i: &rkyv::rend::u32_le| {
+        let station = &all_connections_rkyv.stations[station_i.to_native() as usize];
+        &gtfs_rkyv.stops[station.main_stop_i.to_native() as usize]
+    };
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create(output_path)?);
+    writeln!(file, "origin_stop_id,destination_stop_id,seconds")?;
+    for (origin_i, origin_station_i) in matrix_rkyv.origin_station_indices.iter().enumerate() {
+        let origin_stop = station_stop(origin_station_i);
+        for (destination_i, destination_station_i) in
+            matrix_rkyv.destination_station_indices.iter().enumerate()
+        {
+            let destination_stop = station_stop(destination_station_i);
+            match matrix_rkyv.travel_time(origin_i, destination_i) {
+                Some(travel_time) => writeln!(
+                    file,
+                    "{},{},{}",
+                    origin_stop.id, destination_stop.id, travel_time
+                )?,
+                None => writeln!(file, "{},{},", origin_stop.id, destination_stop.id)?,
+            }
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+fn write_travel_time_matrix_columnar(
+    gtfs_rkyv: &gtfs_rkyv::ArchivedGtfsData,
+    all_connections_rkyv: &prepare_direct_connections_rkyv::ArchivedAllConnections,
+    matrix_rkyv: &ArchivedTravelTimeMatrix,
+    output_path: &Path,
+) -> Result<()> {
+    let station_stop = |station_i: &rkyv::rend::u32_le| {
+        let station = &all_connections_rkyv.stations[station_i.to_native() as usize];
+        &gtfs_rkyv.stops[station.main_stop_i.to_native() as usize]
+    };
+
+    std::fs::create_dir_all(output_path)?;
+    let mut origins = vec![];
+    let mut destinations = vec![];
+    let mut seconds = vec![];
+    for (origin_i, origin_station_i) in matrix_rkyv.origin_station_indices.iter().enumerate() {
+        for (destination_i, destination_station_i) in
+            matrix_rkyv.destination_station_indices.iter().enumerate()
+        {
+            origins.push(origin_station_i.to_native());
+            destinations.push(destination_station_i.to_native());
+            seconds.push(
+                matrix_rkyv
+                    .travel_time(origin_i, destination_i)
+                    .unwrap_or(UNREACHABLE_TRAVEL_TIME),
+            );
+        }
+    }
+    let columns = vec![
+        ColumnarColumn {
+            name: "origin_station_i",
+            data_type: "u32le",
+            file_name: "origin_station_i.bin",
+        },
+        ColumnarColumn {
+            name: "destination_station_i",
+            data_type: "u32le",
+            file_name: "destination_station_i.bin",
+        },
+        ColumnarColumn {
+            name: "seconds",
+            data_type: "u32le",
+            file_name: "seconds.bin",
+        },
+    ];
+    for (column, values) in columns.iter().zip([&origins, &destinations, &seconds]) {
+        let mut file =
+            std::io::BufWriter::new(std::fs::File::create(output_path.join(column.file_name))?);
+        for value in values {
+            file.write_all(&value.to_le_bytes())?;
+        }
+        file.flush()?;
+    }
+    let mut station_indices: Vec<_> = matrix_rkyv
+        .origin_station_indices
+        .iter()
+        .chain(matrix_rkyv.destination_station_indices.iter())
+        .map(|station_i| station_i.to_native())
+        .collect();
+    station_indices.sort_unstable();
+    station_indices.dedup();
+    let schema = ColumnarSchema {
+        rows_num: seconds.len(),
+        unreachable_seconds: UNREACHABLE_TRAVEL_TIME,
+        columns,
+        stations: station_indices
+            .iter()
+            .map(|station_i| {
+                let stop = station_stop(&(*station_i).into());
+                ColumnarStation {
+                    station_i: *station_i,
+                    stop_id: stop.id.to_string(),
+                    name: stop.name.as_ref().map(|name| name.to_string()),
+                }
+            })
+            .collect(),
+    };
+    let mut file = std::fs::File::create(output_path.join("schema.json"))?;
+    file.write_all(serde_json::to_string_pretty(&schema)?.as_bytes())?;
+    Ok(())
+}
+
+/// Runs one search per origin and collects the travel times to all destinations.
+pub fn compute_travel_time_matrix(
+    stations: &[prepare_direct_connections_rkyv::ArchivedConnectionsFromStation],
+    origin_station_indices: &[u32],
+    destination_station_indices: &[u32],
+    connection_filter: &ConnectionFilter,
+) -> TravelTimeMatrix {
+    let mut travel_times =
+        Vec::with_capacity(origin_station_indices.len() * destination_station_indices.len());
+    find_optimal_paths::search_from_each_origin(
+        stations,
+        origin_station_indices,
+        connection_filter,
+        |station_states| {
+            travel_times.extend(
+                destination_station_indices
+                    .iter()
+                    .map(|destination_station_i| {
+                        station_states[*destination_station_i as usize]
+                            .earliest_arrival
+                            .unwrap_or(UNREACHABLE_TRAVEL_TIME)
+                    }),
+            );
+        },
+    );
+
+    TravelTimeMatrix {
+        origin_station_indices: origin_station_indices.to_vec(),
+        destination_station_indices: destination_station_indices.to_vec(),
+        travel_times,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prepare_direct_connections_rkyv::{ConnectionToStation, ConnectionsFromStation};
+
+    /// Stations 0, 1 and 2 in a line, with connections in both directions. Station 3 has no
+    /// connections.
+    fn stations() -> Vec<ConnectionsFromStation> {
+        let connection = |to_station_i: u32, duration| ConnectionToStation {
+            to_station_i,
+            from_stop_i: 0,
+            to_stop_i: to_station_i,
+            duration,
+            route_type_mask: 1,
+            agency_i: prepare_direct_connections_rkyv::NO_AGENCY,
+            wheelchair_accessible: true,
+        };
+        let connections = [
+            vec![connection(1, 600)],
+            vec![connection(0, 600), connection(2, 300)],
+            vec![connection(1, 300)],
+            vec![],
+        ];
+        connections
+            .into_iter()
+            .enumerate()
+            .map(|(station_i, connections)| ConnectionsFromStation {
+                main_stop_i: station_i as u32,
+                connections,
+                transfer_stop_indices: vec![],
+                transfer_times: vec![],
+                step_free_transfer_times: vec![],
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn round_trips_travel_times() {
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&stations()).unwrap();
+        let stations = rkyv::access::<
+            rkyv::Archived<Vec<ConnectionsFromStation>>,
+            rkyv::rancor::Error,
+        >(&bytes)
+        .unwrap();
+        let matrix =
+            compute_travel_time_matrix(stations, &[2, 0], &[0, 1, 3], &ConnectionFilter::default());
+
+        let path = std::env::temp_dir().join(format!(
+            "trip-atlas-travel-time-matrix-{}.bin",
+            std::process::id()
+        ));
+        let buffer = rkyv::to_bytes::<rkyv::rancor::Error>(&matrix).unwrap();
+        std::fs::write(&path, &buffer).unwrap();
+        let matrix_rkyv = unsafe { load_travel_time_matrix(&path).await.unwrap() };
+        std::fs::remove_file(&path).unwrap();
+
+        matrix_rkyv.validate(stations.len()).unwrap();
+        assert!(matrix_rkyv.validate(3).is_err());
+        let travel_times: Vec<Vec<Option<u32>>> = (0..2)
+            .map(|origin_i| {
+                (0..3)
+                    .map(|destination_i| matrix_rkyv.travel_time(origin_i, destination_i))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            travel_times,
+            vec![
+                vec![Some(900), Some(300), None],
+                vec![Some(0), Some(600), None],
+            ]
+        );
+    }
+}
@@ -0,0 +1,421 @@
+use anyhow::Result;
+use std::{io::Write, path::Path};
+
+use crate::{
+    connection_filter::{ConnectionFilter, ConnectionFilterArgs},
+    find_optimal_paths::{self, StationState},
+    gtfs_rkyv,
+    memory_mapped_rkyv::{self, MemoryMappedRkyv},
+    prepare_direct_connections_rkyv, prepare_gtfs_as_rkyv,
+    station_output::{self, StationOutputArgs},
+    varint,
+};
+
+/// Precomputed travel times from a set of origins to every station, so that the result of a
+/// search from one of the origins can be looked up instead of computed.
+///
+/// The travel times of an origin are stored as one varint per station in the order of
+/// `AllConnections.stations`. A value of zero means that the station is unreachable, any other
+/// value is the travel time in units of `seconds_per_unit` plus one.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug)]
+#[rkyv(derive(Debug))]
+pub struct TravelTimeStore {
+    /// Number of stations in the `AllConnections` that the store was computed for.
+    pub stations_num: u32,
+    /// `feed_fingerprint` of the feed that the store was computed for.
+    pub feed_fingerprint: u64,
+    /// `connection_filter_fingerprint` of the filter that the searches used.
+    pub connection_filter_fingerprint: u64,
+    pub seconds_per_unit: u32,
+    /// Sorted indices into `AllConnections.stations`.
+    pub origin_station_indices: Vec<u32>,
+    /// Start of the encoded travel times of every origin, with an additional entry for the end.
+    pub offsets: Vec<u64>,
+    pub encoded_travel_times: Vec<u8>,
+}
+
+impl ArchivedTravelTimeStore {
+    /// Decodes the travel times in seconds from the origin to every station. Returns `None` if
+    /// the station is not one of the stored origins.
+    pub fn travel_times(&self, origin_station_i: u32) -> Result<Option<Vec<Option<u32>>>> {
+        let Ok(origin_i) = self
+            .origin_station_indices
+            .binary_search_by_key(&origin_station_i, |station_i| station_i.to_native())
+        else {
+            return Ok(None);
+        };
+        let invalid = || anyhow::anyhow!("The travel time store is corrupt");
+        let (Some(start), Some(end)) = (self.offsets.get(origin_i), self.offsets.get(origin_i + 1))
+        else {
+            return Err(invalid());
+        };
+        let mut encoded = self
+            .encoded_travel_times
+            .get(start.to_native() as usize..end.to_native() as usize)
+            .ok_or_else(invalid)?;
+        let seconds_per_unit = self.seconds_per_unit.to_native();
+
+        let stations_num = self.stations_num.to_native() as usize;
+        let mut travel_times = Vec::with_capacity(stations_num);
+        while !encoded.is_empty() {
+            let value = varint::read_varint(&mut encoded).ok_or_else(invalid)?;
+            let travel_time = match value.checked_sub(1) {
+                Some(units) => Some(
+                    u32::try_from(units)
+                        .ok()
+                        .and_then(|units| units.checked_mul(seconds_per_unit))
+                        .ok_or_else(invalid)?,
+                ),
+                None => None,
+            };
+            travel_times.push(travel_time);
+        }
+        if travel_times.len() != stations_num {
+            return Err(invalid());
+        }
+        Ok(Some(travel_times))
+    }
+}
+
+// Safety: This is safe for as long as the underlying file is not modified.
+pub async unsafe fn load_travel_time_store(
+    path: &Path,
+) -> Result<MemoryMappedRkyv<'_, ArchivedTravelTimeStore>> {
+    unsafe {
+        memory_mapped_rkyv::load_memory_mapped_rkyv_checked::<ArchivedTravelTimeStore>(path).await
+    }
+}
+
+const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+/// FNV-1a, which unlike the standard library hasher is stable between Rust versions.
+fn hash(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Identifies the stations and connections a store was computed from, so that a store is not
+/// used with a different feed that happens to have the same number of stations. Connections
+/// are combined independently of their order, which is not stable between preparations.
+fn feed_fingerprint(
+    gtfs_rkyv: &gtfs_rkyv::ArchivedGtfsData,
+    stations: &[prepare_direct_connections_rkyv::ArchivedConnectionsFromStation],
+) -> u64 {
+    let mut fingerprint = OFFSET_BASIS;
+    for station in stations {
+        let stop = &gtfs_rkyv.stops[station.main_stop_i.to_native() as usize];
+        fingerprint = hash(fingerprint, stop.id.as_bytes());
+        fingerprint = hash(fingerprint, &[0]);
+        let connections_hash = station
+            .connections
+            .iter()
+            .map(|connection| {
+                let connection_hash = hash(
+                    OFFSET_BASIS,
+                    &connection.to_station_i.to_native().to_le_bytes(),
+                );
+                hash(
+                    connection_hash,
+                    &connection.duration.to_native().to_le_bytes(),
+                )
+            })
+            .fold(0u64, |sum, connection_hash| {
+                sum.wrapping_add(connection_hash)
+            });
+        fingerprint = hash(fingerprint, &connections_hash.to_le_bytes());
+    }
+    fingerprint
+}
+
+/// Identifies the connection filter a store was computed with, so that its travel times are
+/// not mistaken for those of a search with different options.
+fn connection_filter_fingerprint(connection_filter: &ConnectionFilter) -> u64 {
+    let mut fingerprint = hash(
+        OFFSET_BASIS,
+        &connection_filter.allowed_route_types.to_le_bytes(),
+    );
+    match &connection_filter.allowed_agencies {
+        Some(allowed_agencies) => {
+            fingerprint = hash(fingerprint, &[1]);
+            for allowed in allowed_agencies {
+                fingerprint = hash(fingerprint, &[*allowed as u8]);
+            }
+        }
+        None => fingerprint = hash(fingerprint, &[0]),
+    }
+    hash(
+        fingerprint,
+        &[
+            connection_filter.unknown_agency_allowed as u8,
+            connection_filter.step_free as u8,
+            connection_filter.wheelchair_accessible_only as u8,
+        ],
+    )
+}
+
+/// Runs a search from every origin and writes the resulting travel times to a store. Without
+/// origin names, every station is used as an origin.
+pub async fn build_travel_time_store(
+    gtfs_folder_path: &Path,
+    origin_station_names: &[String],
+    output_path: &Path,
+    seconds_per_unit: u32,
+    connection_filter: &ConnectionFilterArgs,
+) -> Result<()> {
+    if seconds_per_unit == 0 {
+        anyhow::bail!("The seconds per unit must be at least one");
+    }
+    let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
+    let all_connections_rkyv =
+        prepare_direct_connections_rkyv::load_direct_connections_rkyv(gtfs_folder_path).await?;
+    let connection_filter = connection_filter.to_filter(&gtfs_rkyv)?;
+    let stations = &all_connections_rkyv.stations;
+
+    let mut origin_station_indices = if origin_station_names.is_empty() {
+        (0..stations.len() as u32).collect()
+    } else {
+        let origin_station_names: Vec<&str> = origin_station_names
+            .iter()
+            .map(|name| name.as_str())
+            .collect();
+        find_optimal_paths::find_station_indices_by_names(
+            &gtfs_rkyv,
+            &all_connections_rkyv,
+            &origin_station_names,
+        )
+    };
+    if origin_station_indices.is_empty() {
+        anyhow::bail!("None of the given station names was found");
+    }
+    origin_station_indices.sort_unstable();
+
+    let store = compute_travel_time_store(
+        stations,
+        origin_station_indices,
+        seconds_per_unit,
+        feed_fingerprint(&gtfs_rkyv, stations),
+        &connection_filter,
+    );
+    let buffer = rkyv::to_bytes::<rkyv::rancor::Error>(&store)?;
+    let mut file = std::fs::File::create(output_path)?;
+    file.write_all(&buffer)?;
+    Ok(())
+}
+
+/// Runs a search from every origin, which are expected to be sorted, and encodes the
+/// resulting travel times.
+fn compute_travel_time_store(
+    stations: &[prepare_direct_connections_rkyv::ArchivedConnectionsFromStation],
+    origin_station_indices: Vec<u32>,
+    seconds_per_unit: u32,
+    feed_fingerprint: u64,
+    connection_filter: &ConnectionFilter,
+) -> TravelTimeStore {
+    let mut offsets = Vec::with_capacity(origin_station_indices.len() + 1);
+    let mut encoded_travel_times = vec![];
+    find_optimal_paths::search_from_each_origin(
+        stations,
+        &origin_station_indices,
+        connection_filter,
+        |station_states| {
+            offsets.push(encoded_travel_times.len() as u64);
+            for station_state in station_states {
+                let value = match station_state.earliest_arrival {
+                    Some(earliest_arrival) => earliest_arrival.div_ceil(seconds_per_unit) + 1,
+                    None => 0,
+                };
+                varint::write_varint(&mut encoded_travel_times, value as u64);
+            }
+        },
+    );
+    offsets.push(encoded_travel_times.len() as u64);
+
+    TravelTimeStore {
+        stations_num: stations.len() as u32,
+        feed_fingerprint,
+        connection_filter_fingerprint: connection_filter_fingerprint(connection_filter),
+        seconds_per_unit,
+        origin_station_indices,
+        offsets,
+        encoded_travel_times,
+    }
+}
+
+/// Looks up the travel times from a station in a previously built store and writes them like
+/// the result of a search.
+pub async fn query_travel_time_store(
+    gtfs_folder_path: &Path,
+    store_path: &Path,
+    origin_station_name: &str,
+    output: &StationOutputArgs,
+    connection_filter: &ConnectionFilterArgs,
+) -> Result<()> {
+    let gtfs_rkyv = prepare_gtfs_as_rkyv::load_gtfs_folder_rkyv(gtfs_folder_path).await?;
+    let all_connections_rkyv =
+        prepare_direct_connections_rkyv::load_direct_connections_rkyv(gtfs_folder_path).await?;
+    let store_rkyv = unsafe { load_travel_time_store(store_path).await? };
+    if store_rkyv.stations_num.to_native() as usize != all_connections_rkyv.stations.len()
+        || store_rkyv.feed_fingerprint.to_native()
+            != feed_fingerprint(&gtfs_rkyv, &all_connections_rkyv.stations)
+    {
+        anyhow::bail!("The travel time store was built for a different GTFS feed");
+    }
+    let connection_filter = connection_filter.to_filter(&gtfs_rkyv)?;
+    if store_rkyv.connection_filter_fingerprint.to_native()
+        != connection_filter_fingerprint(&connection_filter)
+    {
+        anyhow::bail!("The travel time store was built with different connection filter options");
+    }
+
+    let origin_station_indices = find_optimal_paths::find_station_indices_by_names(
+        &gtfs_rkyv,
+        &all_connections_rkyv,
+        &[origin_station_name],
+    );
+    let mut travel_times = None;
+    for station_i in origin_station_indices {
+        travel_times = store_rkyv.travel_times(station_i)?;
+        if travel_times.is_some() {
+            break;
+        }
+    }
+    let Some(travel_times) = travel_times else {
+        anyhow::bail!("The station is not an origin of the travel time store");
+    };
+
+    let station_states: Vec<StationState> = travel_times
+        .into_iter()
+        .map(|earliest_arrival| StationState { earliest_arrival })
+        .collect();
+    let stations = find_optimal_paths::collect_reached_stations(
+        &gtfs_rkyv,
+        &all_connections_rkyv,
+        &station_states,
+    );
+    station_output::write_stations(&stations, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prepare_direct_connections_rkyv::{ConnectionToStation, ConnectionsFromStation};
+
+    /// Station 0 leads to 1 by a connection with mode bit 1, 1 to 2 by mode bit 1 and 2 back to
+    /// 0 by mode bit 2. Station 3 has no connections.
+    fn stations() -> Vec<ConnectionsFromStation> {
+        let connection = |to_station_i: u32, duration, route_type_mask| ConnectionToStation {
+            to_station_i,
+            from_stop_i: 0,
+            to_stop_i: to_station_i,
+            duration,
+            route_type_mask,
+            agency_i: prepare_direct_connections_rkyv::NO_AGENCY,
+            wheelchair_accessible: true,
+        };
+        let connections = [
+            vec![connection(1, 630, 1)],
+            vec![connection(2, 300, 1)],
+            vec![connection(0, 120, 2)],
+            vec![],
+        ];
+        connections
+            .into_iter()
+            .enumerate()
+            .map(|(station_i, connections)| ConnectionsFromStation {
+                main_stop_i: station_i as u32,
+                connections,
+                transfer_stop_indices: vec![],
+                transfer_times: vec![],
+                step_free_transfer_times: vec![],
+            })
+            .collect()
+    }
+
+    async fn write_and_load(
+        store: &TravelTimeStore,
+        name: &str,
+    ) -> (Vec<Option<u32>>, Vec<Option<u32>>, u64) {
+        let path = std::env::temp_dir().join(format!(
+            "trip-atlas-travel-time-store-{}-{}.bin",
+            name,
+            std::process::id()
+        ));
+        let buffer = rkyv::to_bytes::<rkyv::rancor::Error>(store).unwrap();
+        std::fs::write(&path, &buffer).unwrap();
+        let store_rkyv = unsafe { load_travel_time_store(&path).await.unwrap() };
+        assert_eq!(store_rkyv.travel_times(1).unwrap(), None);
+        let result = (
+            store_rkyv.travel_times(0).unwrap().unwrap(),
+            store_rkyv.travel_times(2).unwrap().unwrap(),
+            store_rkyv.connection_filter_fingerprint.to_native(),
+        );
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[tokio::test]
+    async fn round_trips_travel_times() {
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&stations()).unwrap();
+        let stations = rkyv::access::<
+            rkyv::Archived<Vec<ConnectionsFromStation>>,
+            rkyv::rancor::Error,
+        >(&bytes)
+        .unwrap();
+        let connection_filter = ConnectionFilter::default();
+        let store = compute_travel_time_store(stations, vec![0, 2], 60, 42, &connection_filter);
+        assert_eq!(store.feed_fingerprint, 42);
+
+        let (from_0, from_2, filter_fingerprint) = write_and_load(&store, "all").await;
+        // Travel times are rounded up to whole minutes.
+        assert_eq!(from_0, vec![Some(0), Some(660), Some(960), None]);
+        assert_eq!(from_2, vec![Some(120), Some(780), Some(0), None]);
+        assert_eq!(
+            filter_fingerprint,
+            connection_filter_fingerprint(&connection_filter)
+        );
+    }
+
+    #[tokio::test]
+    async fn stores_the_connection_filter() {
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&stations()).unwrap();
+        let stations = rkyv::access::<
+            rkyv::Archived<Vec<ConnectionsFromStation>>,
+            rkyv::rancor::Error,
+        >(&bytes)
+        .unwrap();
+        let connection_filter = ConnectionFilter {
+            allowed_route_types: 1,
+            ..ConnectionFilter::default()
+        };
+        let store = compute_travel_time_store(stations, vec![0, 2], 60, 42, &connection_filter);
+
+        let (from_0, from_2, filter_fingerprint) = write_and_load(&store, "filtered").await;
+        assert_eq!(from_0, vec![Some(0), Some(660), Some(960), None]);
+        assert_eq!(from_2, vec![None, None, Some(0), None]);
+        assert_eq!(
+            filter_fingerprint,
+            connection_filter_fingerprint(&connection_filter)
+        );
+        for other_filter in [
+            ConnectionFilter::default(),
+            ConnectionFilter {
+                allowed_route_types: 1,
+                step_free: true,
+                ..ConnectionFilter::default()
+            },
+            ConnectionFilter {
+                allowed_route_types: 1,
+                allowed_agencies: Some(vec![true]),
+                ..ConnectionFilter::default()
+            },
+        ] {
+            assert_ne!(
+                filter_fingerprint,
+                connection_filter_fingerprint(&other_filter)
+            );
+        }
+    }
+}
@@ -0,0 +1,538 @@
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Write,
+    path::Path,
+};
+
+use crate::timezones;
+
+/// Only the first issues of every kind are listed in a report, the rest are only counted.
+const MAX_ISSUES_PER_KIND: usize = 100;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum ValidationReportFormat {
+    Json,
+    Html,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// A file exists but could not be parsed, or a required file is missing.
+    UnreadableFile,
+    /// Neither `calendar.txt` nor `calendar_dates.txt` exist.
+    MissingServiceDefinitions,
+    DuplicateId,
+    UnknownReference,
+    /// A departure before the arrival at the same stop, or an arrival before the departure at
+    /// the previous stop of the trip.
+    BackwardsTime,
+    ParentStationCycle,
+    MissingCoordinates,
+    /// A timezone that is not in the IANA database, or agencies with different timezones.
+    InvalidTimezone,
+}
+
+impl IssueKind {
+    pub fn severity(self) -> Severity {
+        match self {
+            IssueKind::UnreadableFile
+            | IssueKind::MissingServiceDefinitions
+            | IssueKind::DuplicateId
+            | IssueKind::BackwardsTime
+            | IssueKind::ParentStationCycle
+            | IssueKind::InvalidTimezone => Severity::Error,
+            IssueKind::UnknownReference | IssueKind::MissingCoordinates => Severity::Warning,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ValidationIssue {
+    pub kind: IssueKind,
+    pub severity: Severity,
+    pub file: &'static str,
+    /// Id of the affected entity, if the issue concerns a single one.
+    pub id: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct IssueSummary {
+    pub kind: IssueKind,
+    pub severity: Severity,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct ValidationReport {
+    pub summary: Vec<IssueSummary>,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    fn add(&mut self, kind: IssueKind, file: &'static str, id: Option<&str>, message: String) {
+        let count = match self.summary.iter_mut().find(|summary| summary.kind == kind) {
+            Some(summary) => {
+                summary.count += 1;
+                summary.count
+            }
+            None => {
+                self.summary.push(IssueSummary {
+                    kind,
+                    severity: kind.severity(),
+                    count: 1,
+                });
+                1
+            }
+        };
+        if count <= MAX_ISSUES_PER_KIND {
+            self.issues.push(ValidationIssue {
+                kind,
+                severity: kind.severity(),
+                file,
+                id: id.map(|id| id.to_string()),
+                message,
+            });
+        }
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.summary
+            .iter()
+            .filter(|summary| summary.severity == severity)
+            .map(|summary| summary.count)
+            .sum()
+    }
+}
+
+/// Validates the feed in the folder, prints a summary and optionally writes the full report.
+pub async fn validate_gtfs(
+    gtfs_folder_path: &Path,
+    output_path: Option<&Path>,
+    format: ValidationReportFormat,
+) -> Result<()> {
+    log::info!("Loading original GTFS data from {:?}", gtfs_folder_path);
+    let gtfs = gtfs_structures::RawGtfs::from_path(gtfs_folder_path)?;
+    let report = validate_raw_gtfs(&gtfs);
+
+    println!("{:<30} {:<8} {:>10}", "Issue", "Severity", "Count");
+    for summary in &report.summary {
+        println!(
+            "{:<30} {:<8} {:>10}",
+            format!("{:?}", summary.kind),
+            format!("{:?}", summary.severity),
+            summary.count
+        );
+    }
+    println!(
+        "{} errors, {} warnings",
+        report.count(Severity::Error),
+        report.count(Severity::Warning)
+    );
+
+    if let Some(output_path) = output_path {
+        let content = match format {
+            ValidationReportFormat::Json => serde_json::to_string_pretty(&report)?,
+            ValidationReportFormat::Html => report_to_html(&report),
+        };
+        let mut file = std::fs::File::create(output_path)?;
+        file.write_all(content.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Checks the feed for problems that would otherwise lead to wrong or missing connections.
+pub fn validate_raw_gtfs(gtfs: &gtfs_structures::RawGtfs) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    let mut unreadable_file = |file: &'static str, error: &gtfs_structures::Error| {
+        report.add(IssueKind::UnreadableFile, file, None, error.to_string());
+    };
+    let stops = gtfs
+        .stops
+        .as_ref()
+        .inspect_err(|error| unreadable_file("stops.txt", error))
+        .ok();
+    let routes = gtfs
+        .routes
+        .as_ref()
+        .inspect_err(|error| unreadable_file("routes.txt", error))
+        .ok();
+    let trips = gtfs
+        .trips
+        .as_ref()
+        .inspect_err(|error| unreadable_file("trips.txt", error))
+        .ok();
+    let agencies = gtfs
+        .agencies
+        .as_ref()
+        .inspect_err(|error| unreadable_file("agency.txt", error))
+        .ok();
+    let stop_times = gtfs
+        .stop_times
+        .as_ref()
+        .inspect_err(|error| unreadable_file("stop_times.txt", error))
+        .ok();
+    let calendars = gtfs.calendar.as_ref().and_then(|calendars| {
+        calendars
+            .as_ref()
+            .inspect_err(|error| unreadable_file("calendar.txt", error))
+            .ok()
+    });
+    let shapes = gtfs.shapes.as_ref().and_then(|shapes| {
+        shapes
+            .as_ref()
+            .inspect_err(|error| unreadable_file("shapes.txt", error))
+            .ok()
+    });
+    let calendar_dates = gtfs.calendar_dates.as_ref().and_then(|calendar_dates| {
+        calendar_dates
+            .as_ref()
+            .inspect_err(|error| unreadable_file("calendar_dates.txt", error))
+            .ok()
+    });
+
+    // Feeds may define all services in calendar_dates.txt, but one of the files is required.
+    if gtfs.calendar.is_none() && gtfs.calendar_dates.is_none() {
+        report.add(
+            IssueKind::MissingServiceDefinitions,
+            "calendar.txt",
+            None,
+            "Neither calendar.txt nor calendar_dates.txt exist, so no trip is running on any day"
+                .to_string(),
+        );
+    }
+
+    let stop_ids = check_duplicates(
+        &mut report,
+        "stops.txt",
+        stops
+            .iter()
+            .flat_map(|stops| stops.iter().map(|stop| stop.id.as_str())),
+    );
+    let route_ids = check_duplicates(
+        &mut report,
+        "routes.txt",
+        routes
+            .iter()
+            .flat_map(|routes| routes.iter().map(|route| route.id.as_str())),
+    );
+    let trip_ids = check_duplicates(
+        &mut report,
+        "trips.txt",
+        trips
+            .iter()
+            .flat_map(|trips| trips.iter().map(|trip| trip.id.as_str())),
+    );
+    let agency_ids = check_duplicates(
+        &mut report,
+        "agency.txt",
+        agencies
+            .iter()
+            .flat_map(|agencies| agencies.iter().filter_map(|agency| agency.id.as_deref())),
+    );
+    let mut service_ids = check_duplicates(
+        &mut report,
+        "calendar.txt",
+        calendars
+            .iter()
+            .flat_map(|calendars| calendars.iter().map(|calendar| calendar.id.as_str())),
+    );
+    service_ids.extend(calendar_dates.iter().flat_map(|calendar_dates| {
+        calendar_dates
+            .iter()
+            .map(|calendar_date| calendar_date.service_id.as_str())
+    }));
+
+    // Shapes consist of many rows with the same id, so their ids are not checked for duplicates.
+    let shape_ids: HashSet<&str> = shapes
+        .iter()
+        .flat_map(|shapes| shapes.iter().map(|shape| shape.id.as_str()))
+        .collect();
+
+    let mut unknown_reference = |file: &'static str, id: &str, field: &str, value: &str| {
+        report.add(
+            IssueKind::UnknownReference,
+            file,
+            Some(id),
+            format!("{} {:?} does not exist", field, value),
+        );
+    };
+    for stop in stops.into_iter().flatten() {
+        if let Some(parent_station) = stop.parent_station.as_deref() {
+            if !stop_ids.contains(parent_station) {
+                unknown_reference("stops.txt", &stop.id, "parent_station", parent_station);
+            }
+        }
+    }
+    for route in routes.into_iter().flatten() {
+        if let Some(agency_id) = route.agency_id.as_deref() {
+            if !agency_ids.contains(agency_id) {
+                unknown_reference("routes.txt", &route.id, "agency_id", agency_id);
+            }
+        }
+    }
+    for trip in trips.into_iter().flatten() {
+        if !route_ids.contains(trip.route_id.as_str()) {
+            unknown_reference("trips.txt", &trip.id, "route_id", &trip.route_id);
+        }
+        if !service_ids.contains(trip.service_id.as_str()) {
+            unknown_reference("trips.txt", &trip.id, "service_id", &trip.service_id);
+        }
+        if let Some(shape_id) = trip.shape_id.as_deref() {
+            if !shape_ids.contains(shape_id) {
+                unknown_reference("trips.txt", &trip.id, "shape_id", shape_id);
+            }
+        }
+    }
+
+    let mut stop_times_by_trip: HashMap<&str, Vec<&gtfs_structures::RawStopTime>> = HashMap::new();
+    for stop_time in stop_times.into_iter().flatten() {
+        if !trip_ids.contains(stop_time.trip_id.as_str()) {
+            unknown_reference(
+                "stop_times.txt",
+                &stop_time.trip_id,
+                "trip_id",
+                &stop_time.trip_id,
+            );
+        }
+        if !stop_ids.contains(stop_time.stop_id.as_str()) {
+            unknown_reference(
+                "stop_times.txt",
+                &stop_time.trip_id,
+                "stop_id",
+                &stop_time.stop_id,
+            );
+        }
+        stop_times_by_trip
+            .entry(stop_time.trip_id.as_str())
+            .or_default()
+            .push(stop_time);
+    }
+
+    let mut trip_ids_with_stop_times: Vec<&str> = stop_times_by_trip.keys().copied().collect();
+    trip_ids_with_stop_times.sort_unstable();
+    for trip_id in trip_ids_with_stop_times {
+        let stop_times_in_trip = stop_times_by_trip.get_mut(trip_id).unwrap();
+        stop_times_in_trip.sort_by_key(|stop_time| stop_time.stop_sequence);
+        let mut previous_time: Option<(u32, u16)> = None;
+        for stop_time in stop_times_in_trip.iter() {
+            if let (Some(arrival_time), Some(departure_time)) =
+                (stop_time.arrival_time, stop_time.departure_time)
+            {
+                if departure_time < arrival_time {
+                    report.add(
+                        IssueKind::BackwardsTime,
+                        "stop_times.txt",
+                        Some(trip_id),
+                        format!(
+                            "Departure before arrival at stop_sequence {}",
+                            stop_time.stop_sequence
+                        ),
+                    );
+                }
+            }
+            if let (Some((time, stop_sequence)), Some(arrival_time)) = (
+                previous_time,
+                stop_time.arrival_time.or(stop_time.departure_time),
+            ) {
+                if arrival_time < time {
+                    report.add(
+                        IssueKind::BackwardsTime,
+                        "stop_times.txt",
+                        Some(trip_id),
+                        format!(
+                            "Arrival at stop_sequence {} is before the departure at stop_sequence {}",
+                            stop_time.stop_sequence, stop_sequence
+                        ),
+                    );
+                }
+            }
+            if let Some(time) = stop_time.departure_time.or(stop_time.arrival_time) {
+                previous_time = Some((time, stop_time.stop_sequence));
+            }
+        }
+    }
+
+    if let Some(stops) = stops {
+        let parent_by_stop_id: HashMap<&str, &str> = stops
+            .iter()
+            .filter_map(|stop| Some((stop.id.as_str(), stop.parent_station.as_deref()?)))
+            .collect();
+        for stop in stops {
+            // Every stop of a cycle reports itself, so chains that only lead into a cycle are
+            // not reported.
+            let mut visited = vec![stop.id.as_str()];
+            let mut current = stop.id.as_str();
+            while let Some(parent) = parent_by_stop_id.get(current) {
+                if *parent == stop.id {
+                    report.add(
+                        IssueKind::ParentStationCycle,
+                        "stops.txt",
+                        Some(&stop.id),
+                        format!("The parent stations form a cycle: {}", visited.join(" -> ")),
+                    );
+                    break;
+                }
+                if visited.contains(parent) {
+                    break;
+                }
+                visited.push(parent);
+                current = parent;
+            }
+
+            if stop.latitude.is_none() || stop.longitude.is_none() {
+                report.add(
+                    IssueKind::MissingCoordinates,
+                    "stops.txt",
+                    Some(&stop.id),
+                    "The stop has no coordinates and is left out of all map exports".to_string(),
+                );
+            }
+
+            if let Some(timezone) = stop.timezone.as_deref() {
+                if timezones::parse_timezone(timezone).is_err() {
+                    report.add(
+                        IssueKind::InvalidTimezone,
+                        "stops.txt",
+                        Some(&stop.id),
+                        format!("stop_timezone {:?} is not a known timezone", timezone),
+                    );
+                }
+            }
+        }
+    }
+
+    // Stop times are given in the agency timezone, which is why it has to be the same for all.
+    let mut feed_timezone: Option<&str> = None;
+    for agency in agencies.into_iter().flatten() {
+        let id = agency.id.as_deref().unwrap_or(&agency.name);
+        if timezones::parse_timezone(&agency.timezone).is_err() {
+            report.add(
+                IssueKind::InvalidTimezone,
+                "agency.txt",
+                Some(id),
+                format!(
+                    "agency_timezone {:?} is not a known timezone",
+                    agency.timezone
+                ),
+            );
+        }
+        match feed_timezone {
+            None => feed_timezone = Some(&agency.timezone),
+            Some(feed_timezone) if feed_timezone != agency.timezone => report.add(
+                IssueKind::InvalidTimezone,
+                "agency.txt",
+                Some(id),
+                format!(
+                    "agency_timezone {:?} differs from {:?} of the first agency",
+                    agency.timezone, feed_timezone
+                ),
+            ),
+            Some(_) => {}
+        }
+    }
+
+    report.summary.sort_by_key(|summary| summary.kind);
+    report
+}
+
+/// Reports ids that occur more than once and returns the set of all ids.
+fn check_duplicates<'a>(
+    report: &mut ValidationReport,
+    file: &'static str,
+    ids: impl Iterator<Item = &'a str>,
+) -> HashSet<&'a str> {
+    let mut seen_ids = HashSet::new();
+    for id in ids {
+        if !seen_ids.insert(id) {
+            report.add(
+                IssueKind::DuplicateId,
+                file,
+                Some(id),
+                format!("The id {:?} is used more than once", id),
+            );
+        }
+    }
+    seen_ids
+}
+
+fn report_to_html(report: &ValidationReport) -> String {
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    };
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>GTFS validation report</title>\n");
+    html.push_str(
+        "<style>body { font-family: sans-serif; } td, th { padding: 2px 8px; text-align: left; } \
+         .error { color: #b00020; } .warning { color: #a05a00; }</style>\n",
+    );
+    html.push_str("</head>\n<body>\n<h1>GTFS validation report</h1>\n");
+    html.push_str(&format!(
+        "<p>{} errors, {} warnings</p>\n",
+        report.count(Severity::Error),
+        report.count(Severity::Warning)
+    ));
+
+    html.push_str("<h2>Summary</h2>\n<table>\n");
+    html.push_str("<tr><th>Issue</th><th>Severity</th><th>Count</th></tr>\n");
+    for summary in &report.summary {
+        html.push_str(&format!(
+            "<tr class=\"{severity}\"><td>{:?}</td><td>{severity}</td><td>{}</td></tr>\n",
+            summary.kind,
+            summary.count,
+            severity = severity_name(summary.severity)
+        ));
+    }
+    html.push_str("</table>\n");
+
+    // Group the listed issues by kind, following the order of the summary.
+    let mut issues_by_kind: BTreeMap<IssueKind, Vec<&ValidationIssue>> = BTreeMap::new();
+    for issue in &report.issues {
+        issues_by_kind.entry(issue.kind).or_default().push(issue);
+    }
+    for summary in &report.summary {
+        let issues = &issues_by_kind[&summary.kind];
+        html.push_str(&format!("<h2>{:?}</h2>\n", summary.kind));
+        if summary.count > issues.len() {
+            html.push_str(&format!(
+                "<p>Showing the first {} of {} issues.</p>\n",
+                issues.len(),
+                summary.count
+            ));
+        }
+        html.push_str("<table>\n<tr><th>File</th><th>Id</th><th>Message</th></tr>\n");
+        for issue in issues {
+            html.push_str(&format!(
+                "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                severity_name(issue.severity),
+                issue.file,
+                escape(issue.id.as_deref().unwrap_or("")),
+                escape(&issue.message)
+            ));
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Warning => "warning",
+        Severity::Error => "error",
+    }
+}
@@ -0,0 +1,67 @@
+/// Appends the value as a LEB128 varint, as used by protocol buffers.
+pub fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Reads a varint from the start of the buffer and advances the buffer past it. Returns `None`
+/// if the buffer ends within the varint or the varint does not fit into 64 bits.
+pub fn read_varint(buffer: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    while let Some((byte, rest)) = buffer.split_first() {
+        *buffer = rest;
+        if shift >= 64 || (shift == 63 && byte & 0x7e != 0) {
+            return None;
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let values = [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX];
+        let mut buffer = vec![];
+        for value in values {
+            write_varint(&mut buffer, value);
+        }
+        let mut encoded = buffer.as_slice();
+        for value in values {
+            assert_eq!(read_varint(&mut encoded), Some(value));
+        }
+        assert!(encoded.is_empty());
+    }
+
+    #[test]
+    fn encodes_like_protocol_buffers() {
+        let mut buffer = vec![];
+        write_varint(&mut buffer, 300);
+        assert_eq!(buffer, [0xac, 0x02]);
+    }
+
+    #[test]
+    fn rejects_truncated_varint() {
+        assert_eq!(read_varint(&mut [0x80, 0x80].as_slice()), None);
+        assert_eq!(read_varint(&mut [].as_slice()), None);
+    }
+
+    #[test]
+    fn rejects_overlong_varint() {
+        assert_eq!(read_varint(&mut [0xff; 11].as_slice()), None);
+        let mut too_large = vec![0xff; 9];
+        too_large.push(0x02);
+        assert_eq!(read_varint(&mut too_large.as_slice()), None);
+    }
+}
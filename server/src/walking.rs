@@ -0,0 +1,27 @@
+/// Approximate length of one degree of latitude. Good enough for the short distances that are
+/// walked from a station.
+pub const METERS_PER_DEGREE_LATITUDE: f64 = 111_320.0;
+
+/// Average walking speed in meters per second.
+pub const DEFAULT_WALKING_SPEED: f64 = 1.25;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct WalkingArgs {
+    /// Walking speed in meters per second.
+    #[arg(long, default_value_t = DEFAULT_WALKING_SPEED)]
+    pub walking_speed: f64,
+    /// Maximum distance in meters that is walked from a station.
+    #[arg(long, default_value_t = 1000.0)]
+    pub max_walking_distance: f64,
+}
+
+impl WalkingArgs {
+    /// Distance that can be walked from a station in the given remaining time.
+    pub fn walking_distance(&self, remaining_seconds: u32) -> f64 {
+        (remaining_seconds as f64 * self.walking_speed).min(self.max_walking_distance)
+    }
+}
+
+pub fn meters_per_degree_longitude(latitude: f64) -> f64 {
+    METERS_PER_DEGREE_LATITUDE * latitude.to_radians().cos()
+}
@@ -0,0 +1,22 @@
+/// Web Mercator cannot represent the poles, latitudes are clamped to this range.
+const MAX_LATITUDE: f64 = 85.051_128_78;
+
+/// Largest zoom level whose number of tiles per axis fits into a `u32`.
+pub const MAX_ZOOM: u8 = 31;
+
+/// Returns the `x` and `y` index of the tile at the given zoom level that contains the
+/// location, using the same scheme as OpenStreetMap tiles.
+pub fn tile_for_location(longitude: f64, latitude: f64, zoom: u8) -> (u32, u32) {
+    let (x, y) = location_to_unit_square(longitude, latitude);
+    let tiles_num = 1u32 << zoom;
+    let to_tile_i = |value: f64| ((value * tiles_num as f64) as u32).min(tiles_num - 1);
+    (to_tile_i(x), to_tile_i(y))
+}
+
+/// Projects the location into the unit square with the origin at the top left.
+pub fn location_to_unit_square(longitude: f64, latitude: f64) -> (f64, f64) {
+    let latitude = latitude.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let x = (longitude + 180.0) / 360.0;
+    let y = (1.0 - (latitude.tan() + 1.0 / latitude.cos()).ln() / std::f64::consts::PI) / 2.0;
+    (x.clamp(0.0, 1.0), y.clamp(0.0, 1.0))
+}